@@ -0,0 +1,343 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec;
+use bootloader::{entry_point, BootInfo};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    arch::x86_64::_rdtsc,
+    panic::PanicInfo,
+    ptr,
+};
+use os::allocator::{
+    bump::BumpAllocator,
+    fixed_size_block::FixedSizeBlockAllocator,
+    linked_list::{Fit, LinkedListAllocator},
+    tlsf::TlsfAllocator,
+    upstream::UpstreamHeap,
+    HeapStatistics, HeapStats, Locked,
+};
+use os::{exit_qemu, serial_println, QemuExitCode};
+use x86_64::instructions::interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os::test_boot(boot_info);
+
+    for (allocator, run) in ALLOCATORS {
+        for (workload, body) in WORKLOADS {
+            let result = run(body);
+            serial_println!("bench allocator={} workload={} {}", allocator, workload, result);
+        }
+    }
+    exit_qemu(QemuExitCode::Success);
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+/// Size of the heap every allocator runs on.
+const ARENA_SIZE: usize = 1024 * 1024;
+
+/// Heap statistics are sampled after this many operations, in a separate
+/// run from the timed one.
+const SAMPLE_INTERVAL: u64 = 32;
+
+/// Upper bound for the number of live allocations of a workload.
+const MAX_LIVE: usize = 1024;
+
+#[repr(C, align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+
+/// Backing memory for the allocators under test. It is separate from the
+/// kernel heap, which holds the bookkeeping of the workloads.
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+type Workload = fn(&mut Bench);
+type Runner = fn(Workload) -> Measurement;
+
+/// The heap allocators of `os::allocator`, each created on the arena.
+const ALLOCATORS: [(&str, Runner); 6] = [
+    ("bump", |body| measure(BumpAllocator::new, BumpAllocator::init, body)),
+    ("linked-list-first-fit", |body| {
+        measure(|| LinkedListAllocator::with_fit(Fit::First), LinkedListAllocator::init, body)
+    }),
+    ("linked-list-best-fit", |body| {
+        measure(|| LinkedListAllocator::with_fit(Fit::Best), LinkedListAllocator::init, body)
+    }),
+    ("fixed-size-block", |body| {
+        measure(FixedSizeBlockAllocator::new, FixedSizeBlockAllocator::init, body)
+    }),
+    ("upstream-linked-list", |body| measure(UpstreamHeap::new, UpstreamHeap::init, body)),
+    ("tlsf", |body| measure(TlsfAllocator::new, TlsfAllocator::init, body)),
+];
+
+const WORKLOADS: [(&str, Workload); 4] = [
+    ("small-boxes", small_boxes),
+    ("mixed-sizes", mixed_sizes),
+    ("producer-consumer", producer_consumer),
+    ("vec-growth", vec_growth),
+];
+
+/// The result of one workload on one allocator.
+struct Measurement {
+    ops: u64,
+    failed: u64,
+    cycles: u64,
+    /// The highest share of free memory, in per mille, that was not part of
+    /// the largest free block.
+    peak_fragmentation: u64,
+}
+
+impl core::fmt::Display for Measurement {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let tenths = self.cycles * 10 / self.ops.max(1);
+        write!(
+            f,
+            "ops={} failed={} cycles={} cycles_per_op={}.{} peak_fragmentation={}.{:03}",
+            self.ops,
+            self.failed,
+            self.cycles,
+            tenths / 10,
+            tenths % 10,
+            self.peak_fragmentation / 1000,
+            self.peak_fragmentation % 1000
+        )
+    }
+}
+
+/// Runs the workload twice on a fresh allocator: once timed, and once with
+/// heap statistics sampled between the operations.
+fn measure<A>(
+    new: fn() -> A,
+    init: unsafe fn(&mut A, usize, usize),
+    body: Workload,
+) -> Measurement
+where
+    A: HeapStatistics,
+    Locked<A>: GlobalAlloc,
+{
+    let mut slots = vec![Slot::EMPTY; MAX_LIVE];
+    let mut run = |sample| {
+        let heap = Locked::new(new());
+        unsafe { init(&mut heap.lock(), &raw mut ARENA as usize, ARENA_SIZE) };
+        slots.fill(Slot::EMPTY);
+        let mut bench = Bench::new(&heap, &mut slots, sample);
+        // keep timer interrupts out of the measurement
+        let cycles = interrupts::without_interrupts(|| {
+            let start = unsafe { _rdtsc() };
+            body(&mut bench);
+            unsafe { _rdtsc() - start }
+        });
+        (bench.ops, bench.failed, cycles, bench.peak_fragmentation)
+    };
+    let (ops, failed, cycles, _) = run(false);
+    let (_, _, _, peak_fragmentation) = run(true);
+    Measurement { ops, failed, cycles, peak_fragmentation }
+}
+
+trait BenchHeap: GlobalAlloc {
+    fn stats(&self) -> HeapStats;
+}
+
+impl<A: HeapStatistics> BenchHeap for Locked<A>
+where
+    Locked<A>: GlobalAlloc,
+{
+    fn stats(&self) -> HeapStats {
+        Locked::stats(self)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl Slot {
+    const EMPTY: Slot = Slot { ptr: ptr::null_mut(), layout: Layout::new::<u8>() };
+
+    fn is_empty(&self) -> bool {
+        self.ptr.is_null()
+    }
+}
+
+/// Performs the operations of a workload and counts them.
+struct Bench<'a> {
+    heap: &'a dyn BenchHeap,
+    /// The live allocations, in an order that the workload chooses.
+    slots: &'a mut [Slot],
+    rng: Rng,
+    ops: u64,
+    failed: u64,
+    sample: bool,
+    peak_fragmentation: u64,
+}
+
+impl<'a> Bench<'a> {
+    fn new(heap: &'a dyn BenchHeap, slots: &'a mut [Slot], sample: bool) -> Self {
+        Bench {
+            heap,
+            slots,
+            rng: Rng(0x2545_f491_4f6c_dd1d),
+            ops: 0,
+            failed: 0,
+            sample,
+            peak_fragmentation: 0,
+        }
+    }
+
+    /// Allocates into the given slot, which stays empty if the heap is
+    /// exhausted.
+    fn alloc(&mut self, slot: usize, size: usize, align: usize) {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let ptr = unsafe { self.heap.alloc(layout) };
+        if ptr.is_null() {
+            self.failed += 1;
+        }
+        self.slots[slot] = Slot { ptr, layout };
+        self.count();
+    }
+
+    /// Frees the allocation in the given slot, if there is one.
+    fn free(&mut self, slot: usize) {
+        let Slot { ptr, layout } = self.slots[slot];
+        if ptr.is_null() {
+            return;
+        }
+        unsafe { self.heap.dealloc(ptr, layout) };
+        self.slots[slot] = Slot::EMPTY;
+        self.count();
+    }
+
+    fn realloc(&mut self, slot: usize, new_size: usize) {
+        let Slot { ptr, layout } = self.slots[slot];
+        if ptr.is_null() {
+            return self.alloc(slot, new_size, layout.align());
+        }
+        let new_ptr = unsafe { self.heap.realloc(ptr, layout, new_size) };
+        if new_ptr.is_null() {
+            self.failed += 1;
+        } else {
+            let layout = Layout::from_size_align(new_size, layout.align()).unwrap();
+            self.slots[slot] = Slot { ptr: new_ptr, layout };
+        }
+        self.count();
+    }
+
+    fn free_all(&mut self) {
+        for slot in 0..self.slots.len() {
+            self.free(slot);
+        }
+    }
+
+    fn count(&mut self) {
+        self.ops += 1;
+        if self.sample && self.ops.is_multiple_of(SAMPLE_INTERVAL) {
+            let stats = self.heap.stats();
+            if stats.free_bytes > 0 {
+                let largest = stats.largest_free_block as u64 * 1000 / stats.free_bytes as u64;
+                let fragmentation = 1000 - largest;
+                self.peak_fragmentation = self.peak_fragmentation.max(fragmentation);
+            }
+        }
+    }
+}
+
+/// A xorshift generator, so that every allocator sees the same operations.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a number in `range`.
+    fn range(&mut self, range: core::ops::Range<usize>) -> usize {
+        range.start + self.next() as usize % (range.end - range.start)
+    }
+}
+
+/// Allocates many small boxes and frees them in random order.
+fn small_boxes(bench: &mut Bench) {
+    const SIZES: [usize; 6] = [8, 16, 24, 32, 48, 64];
+    for _ in 0..20 {
+        for slot in 0..MAX_LIVE {
+            let size = SIZES[bench.rng.range(0..SIZES.len())];
+            bench.alloc(slot, size, 8);
+        }
+        for live in (1..=MAX_LIVE).rev() {
+            let slot = bench.rng.range(0..live);
+            bench.slots.swap(slot, live - 1);
+            bench.free(live - 1);
+        }
+    }
+}
+
+/// Frees or allocates random slots with mostly small, some medium and a few
+/// large sizes.
+fn mixed_sizes(bench: &mut Bench) {
+    for _ in 0..40_000 {
+        let slot = bench.rng.range(0..MAX_LIVE / 2);
+        if !bench.slots[slot].is_empty() {
+            bench.free(slot);
+            continue;
+        }
+        let size = match bench.rng.range(0..100) {
+            0..70 => bench.rng.range(8..128),
+            70..95 => bench.rng.range(128..2048),
+            _ => bench.rng.range(2048..16384),
+        };
+        let align = if bench.rng.range(0..16) == 0 { 64 } else { 8 };
+        bench.alloc(slot, size, align);
+    }
+    bench.free_all();
+}
+
+/// Passes messages through a queue whose length varies, so that allocations
+/// of different lifetimes are interleaved.
+fn producer_consumer(bench: &mut Bench) {
+    let (mut head, mut tail) = (0, 0);
+    for _ in 0..40 {
+        // the producer runs ahead by a random number of messages, then the
+        // consumer catches up partly
+        for _ in 0..bench.rng.range(0..MAX_LIVE - (tail - head) + 1) {
+            let size = bench.rng.range(32..1024);
+            bench.alloc(tail % MAX_LIVE, size, 8);
+            tail += 1;
+        }
+        for _ in 0..bench.rng.range(0..tail - head + 1) {
+            bench.free(head % MAX_LIVE);
+            head += 1;
+        }
+    }
+    bench.free_all();
+}
+
+/// Grows several vectors by doubling their capacity, like `Vec::push` does,
+/// and starts over once they reach 32 KiB.
+fn vec_growth(bench: &mut Bench) {
+    const VECS: usize = 8;
+    for _ in 0..2000 {
+        for slot in 0..VECS {
+            let size = bench.slots[slot].layout.size();
+            if bench.slots[slot].is_empty() || size >= 32 * 1024 {
+                bench.free(slot);
+                bench.alloc(slot, 16, 8);
+            } else {
+                bench.realloc(slot, size * 2);
+            }
+        }
+    }
+    bench.free_all();
+}
@@ -0,0 +1,12 @@
+// Fuzz target for the allocators: reads an operation sequence from stdin and
+// checks it against every allocator, aborting on the first violated
+// invariant. This works with fuzzers that feed inputs over stdin, like AFL,
+// and for replaying a crashing input.
+
+use std::io::Read;
+
+fn main() {
+    let mut data = Vec::new();
+    std::io::stdin().read_to_end(&mut data).expect("failed to read stdin");
+    host_tests::check::check_all(&data);
+}
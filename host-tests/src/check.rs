@@ -0,0 +1,347 @@
+use crate::{
+    Locked,
+    bump::BumpAllocator,
+    fixed_size_block::FixedSizeBlockAllocator,
+    HeapStats,
+    linked_list::{Fit, LinkedListAllocator},
+    tlsf::{TlsfAllocator, BLOCK_OVERHEAD},
+    upstream::UpstreamHeap,
+};
+use std::alloc::{GlobalAlloc, Layout};
+
+/// Size of the heap the allocators are tested on.
+pub const ARENA_SIZE: usize = 64 * 1024;
+
+/// A page aligned byte array that serves as the heap.
+pub struct Arena {
+    start: *mut u8,
+}
+
+impl Arena {
+    const LAYOUT: Layout = match Layout::from_size_align(ARENA_SIZE, 4096) {
+        Ok(layout) => layout,
+        Err(_) => panic!("invalid arena layout"),
+    };
+
+    pub fn new() -> Self {
+        let start = unsafe { std::alloc::alloc_zeroed(Self::LAYOUT) };
+        assert!(!start.is_null());
+        Arena { start }
+    }
+
+    pub fn start(&self) -> usize {
+        self.start as usize
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.start, Self::LAYOUT) };
+    }
+}
+
+/// An allocator that can be tested on an arena.
+pub trait TestHeap: GlobalAlloc {
+    const NAME: &'static str;
+
+    fn new(arena: &Arena) -> Self;
+
+    fn stats(&self) -> HeapStats;
+
+    /// Checks that all memory can be allocated again once everything was
+    /// freed.
+    fn check_recovered(&self) {
+        let stats = self.stats();
+        assert_eq!(stats.live_allocations, 0, "{}: {}", Self::NAME, stats);
+        assert_eq!(stats.allocated_bytes, 0, "{}: {}", Self::NAME, stats);
+        assert_eq!(stats.free_bytes, stats.heap_size, "{}: {}", Self::NAME, stats);
+        assert_eq!(stats.largest_free_block, stats.heap_size, "{}: {}", Self::NAME, stats);
+    }
+}
+
+impl TestHeap for Locked<BumpAllocator> {
+    const NAME: &'static str = "bump";
+
+    fn new(arena: &Arena) -> Self {
+        let heap = Locked::new(BumpAllocator::new());
+        unsafe { heap.lock().init(arena.start(), ARENA_SIZE) };
+        heap
+    }
+
+    fn stats(&self) -> HeapStats {
+        Locked::stats(self)
+    }
+}
+
+/// The linked list allocator with first fit.
+pub struct FirstFit(Locked<LinkedListAllocator>);
+
+/// The linked list allocator with best fit.
+pub struct BestFit(Locked<LinkedListAllocator>);
+
+macro_rules! linked_list_heap {
+    ($name:ident, $fit:expr, $label:literal) => {
+        impl TestHeap for $name {
+            const NAME: &'static str = $label;
+
+            fn new(arena: &Arena) -> Self {
+                let heap = Locked::new(LinkedListAllocator::with_fit($fit));
+                unsafe { heap.lock().init(arena.start(), ARENA_SIZE) };
+                $name(heap)
+            }
+
+            fn stats(&self) -> HeapStats {
+                self.0.stats()
+            }
+        }
+
+        unsafe impl GlobalAlloc for $name {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                unsafe { self.0.alloc(layout) }
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                unsafe { self.0.dealloc(ptr, layout) }
+            }
+
+            unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+                unsafe { self.0.realloc(ptr, layout, new_size) }
+            }
+        }
+    };
+}
+
+linked_list_heap!(FirstFit, Fit::First, "linked list (first fit)");
+linked_list_heap!(BestFit, Fit::Best, "linked list (best fit)");
+
+impl TestHeap for Locked<FixedSizeBlockAllocator> {
+    const NAME: &'static str = "fixed-size block";
+
+    fn new(arena: &Arena) -> Self {
+        let heap = Locked::new(FixedSizeBlockAllocator::new());
+        unsafe { heap.lock().init(arena.start(), ARENA_SIZE) };
+        heap
+    }
+
+    fn stats(&self) -> HeapStats {
+        Locked::stats(self)
+    }
+
+    /// Every size class may keep one page after all of its blocks were freed.
+    fn check_recovered(&self) {
+        let stats = self.stats();
+        assert_eq!(stats.live_allocations, 0, "{}: {}", Self::NAME, stats);
+        assert_eq!(stats.allocated_bytes, 0, "{}: {}", Self::NAME, stats);
+        let classes = self.lock().size_classes();
+        for class in classes.iter() {
+            assert!(class.pages <= 1, "{}: {:?}", Self::NAME, class);
+        }
+        let kept: usize = classes.iter().map(|c| c.pages * c.page_size).sum();
+        assert_eq!(stats.free_bytes + kept, stats.heap_size, "{}: {}", Self::NAME, stats);
+    }
+}
+
+impl TestHeap for Locked<UpstreamHeap> {
+    const NAME: &'static str = "upstream linked list";
+
+    fn new(arena: &Arena) -> Self {
+        let heap = Locked::new(UpstreamHeap::new());
+        unsafe { heap.lock().init(arena.start(), ARENA_SIZE) };
+        heap
+    }
+
+    fn stats(&self) -> HeapStats {
+        Locked::stats(self)
+    }
+
+    /// The upstream heap does not always merge its holes into one again, so
+    /// only the number of free bytes is checked.
+    fn check_recovered(&self) {
+        let stats = self.stats();
+        assert_eq!(stats.live_allocations, 0, "{}: {}", Self::NAME, stats);
+        assert_eq!(stats.free_bytes, stats.heap_size, "{}: {}", Self::NAME, stats);
+    }
+}
+
+impl TestHeap for Locked<TlsfAllocator> {
+    const NAME: &'static str = "TLSF";
+
+    fn new(arena: &Arena) -> Self {
+        let heap = Locked::new(TlsfAllocator::new());
+        unsafe { heap.lock().init(arena.start(), ARENA_SIZE) };
+        heap
+    }
+
+    fn stats(&self) -> HeapStats {
+        Locked::stats(self)
+    }
+
+    /// The heap must be a single free block again, which loses its header
+    /// and the end of the heap to block headers.
+    fn check_recovered(&self) {
+        let stats = self.stats();
+        assert_eq!(stats.live_allocations, 0, "{}: {}", Self::NAME, stats);
+        assert_eq!(stats.allocated_bytes, 0, "{}: {}", Self::NAME, stats);
+        let whole = stats.heap_size - 2 * BLOCK_OVERHEAD;
+        assert_eq!(stats.free_bytes, whole, "{}: {}", Self::NAME, stats);
+        assert_eq!(stats.largest_free_block, whole, "{}: {}", Self::NAME, stats);
+    }
+}
+
+/// One step of an allocation sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Alloc { size: usize, align: usize },
+    /// Frees the live allocation with the given index, modulo their number.
+    Free { index: usize },
+    Realloc { index: usize, new_size: usize },
+}
+
+/// Decodes arbitrary bytes into operations, four bytes per operation.
+pub fn decode(data: &[u8]) -> impl Iterator<Item = Op> + '_ {
+    data.chunks_exact(4).map(|chunk| {
+        let value = u16::from_le_bytes([chunk[1], chunk[2]]) as usize;
+        // mostly small sizes, some large ones
+        let size = if chunk[0] & 0x80 == 0 { 1 + value % 512 } else { 1 + value % 8192 };
+        match chunk[0] % 3 {
+            0 => Op::Alloc { size, align: 1 << (chunk[3] % 8) },
+            1 => Op::Free { index: value },
+            _ => Op::Realloc { index: chunk[3] as usize, new_size: size },
+        }
+    })
+}
+
+struct Allocation {
+    ptr: *mut u8,
+    layout: Layout,
+    fill: u8,
+}
+
+/// Runs operations against an allocator and checks its invariants: every
+/// allocation is aligned and lies within the arena, allocations never
+/// overlap, their contents survive other operations and reallocation, and
+/// all memory can be allocated again once everything was freed.
+pub struct Checker<H> {
+    heap: H,
+    arena: Arena,
+    live: Vec<Allocation>,
+    next_fill: u8,
+}
+
+impl<H: TestHeap> Checker<H> {
+    pub fn new() -> Self {
+        let arena = Arena::new();
+        Checker { heap: H::new(&arena), arena, live: Vec::new(), next_fill: 1 }
+    }
+
+    pub fn run(&mut self, ops: impl IntoIterator<Item = Op>) {
+        for op in ops {
+            self.step(op);
+        }
+        self.free_all();
+        self.heap.check_recovered();
+    }
+
+    fn step(&mut self, op: Op) {
+        match op {
+            Op::Alloc { size, align } => {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let ptr = unsafe { self.heap.alloc(layout) };
+                // running out of memory is allowed
+                if !ptr.is_null() {
+                    self.add(ptr, layout);
+                }
+            }
+            Op::Free { index } if !self.live.is_empty() => {
+                let allocation = self.live.swap_remove(index % self.live.len());
+                self.check_contents(&allocation, allocation.layout.size());
+                unsafe { self.heap.dealloc(allocation.ptr, allocation.layout) };
+            }
+            Op::Realloc { index, new_size } if !self.live.is_empty() => {
+                let index = index % self.live.len();
+                let old = &self.live[index];
+                self.check_contents(old, old.layout.size());
+                let ptr = unsafe { self.heap.realloc(old.ptr, old.layout, new_size) };
+                if ptr.is_null() {
+                    // the old allocation must be untouched
+                    self.check_contents(&self.live[index], self.live[index].layout.size());
+                    return;
+                }
+                let old = self.live.swap_remove(index);
+                let layout = Layout::from_size_align(new_size, old.layout.align()).unwrap();
+                let moved = Allocation { ptr, layout, fill: old.fill };
+                self.check_contents(&moved, old.layout.size().min(new_size));
+                self.add(ptr, layout);
+            }
+            Op::Free { .. } | Op::Realloc { .. } => {}
+        }
+    }
+
+    fn add(&mut self, ptr: *mut u8, layout: Layout) {
+        let name = H::NAME;
+        let start = ptr as usize;
+        let end = start + layout.size();
+        assert_eq!(start % layout.align(), 0, "{name}: {ptr:p} is not aligned for {layout:?}");
+        assert!(
+            start >= self.arena.start() && end <= self.arena.start() + ARENA_SIZE,
+            "{name}: {ptr:p} with {layout:?} is outside of the arena"
+        );
+        for other in &self.live {
+            let other_start = other.ptr as usize;
+            let other_end = other_start + other.layout.size();
+            assert!(
+                end <= other_start || start >= other_end,
+                "{name}: {ptr:p} with {layout:?} overlaps {:p} with {:?}",
+                other.ptr,
+                other.layout
+            );
+        }
+        let fill = self.next_fill;
+        self.next_fill = self.next_fill.wrapping_add(1).max(1);
+        unsafe { ptr.write_bytes(fill, layout.size()) };
+        self.live.push(Allocation { ptr, layout, fill });
+    }
+
+    /// Checks that the first `len` bytes still hold the allocation's pattern.
+    fn check_contents(&self, allocation: &Allocation, len: usize) {
+        let bytes = unsafe { std::slice::from_raw_parts(allocation.ptr, len) };
+        if let Some(offset) = bytes.iter().position(|&b| b != allocation.fill) {
+            panic!(
+                "{}: byte {} of {:p} with {:?} was overwritten",
+                H::NAME,
+                offset,
+                allocation.ptr,
+                allocation.layout
+            );
+        }
+    }
+
+    fn free_all(&mut self) {
+        while let Some(allocation) = self.live.pop() {
+            self.check_contents(&allocation, allocation.layout.size());
+            unsafe { self.heap.dealloc(allocation.ptr, allocation.layout) };
+        }
+    }
+}
+
+impl<H: TestHeap> Default for Checker<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs the operations encoded in `data` against every allocator.
+pub fn check_all(data: &[u8]) {
+    Checker::<Locked<BumpAllocator>>::new().run(decode(data));
+    Checker::<FirstFit>::new().run(decode(data));
+    Checker::<BestFit>::new().run(decode(data));
+    Checker::<Locked<FixedSizeBlockAllocator>>::new().run(decode(data));
+    Checker::<Locked<UpstreamHeap>>::new().run(decode(data));
+    Checker::<Locked<TlsfAllocator>>::new().run(decode(data));
+}
@@ -0,0 +1,23 @@
+// The allocator modules of the kernel, built for the host. They only depend
+// on the items of `allocator::common`, which is included at the crate root
+// so that their `super::` paths resolve.
+
+extern crate alloc;
+
+#[path = "../../src/allocator/common.rs"]
+mod common;
+#[path = "../../src/allocator/bump.rs"]
+pub mod bump;
+#[path = "../../src/allocator/linked_list.rs"]
+pub mod linked_list;
+#[path = "../../src/allocator/fixed_size_block.rs"]
+pub mod fixed_size_block;
+#[path = "../../src/allocator/upstream.rs"]
+pub mod upstream;
+#[path = "../../src/allocator/tlsf.rs"]
+pub mod tlsf;
+
+pub mod check;
+
+pub use common::{Growable, HeapStatistics, HeapStats, Locked};
+use common::{align_up, realloc_by_copy, Usage};
@@ -0,0 +1,57 @@
+use host_tests::{
+    Locked,
+    check::{self, Checker, Op},
+    fixed_size_block::FixedSizeBlockAllocator,
+    tlsf::TlsfAllocator,
+};
+
+/// A xorshift generator, to produce reproducible operation sequences.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+#[test]
+fn random_sequences() {
+    for seed in 1..=200u64 {
+        let data = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15)).bytes(4 * 500);
+        check::check_all(&data);
+    }
+}
+
+#[test]
+fn fill_and_drain() {
+    // allocates until the heap is exhausted, then frees everything
+    let mut ops: Vec<Op> = (0..2000).map(|i| Op::Alloc { size: 1 + i % 300, align: 8 }).collect();
+    ops.extend((0..2000).map(|_| Op::Free { index: 0 }));
+    Checker::<Locked<FixedSizeBlockAllocator>>::new().run(ops.iter().copied());
+    let data: Vec<u8> = ops
+        .iter()
+        .flat_map(|op| match op {
+            Op::Alloc { size, .. } => [0, *size as u8, (*size >> 8) as u8, 3],
+            _ => [1, 0, 0, 0],
+        })
+        .collect();
+    check::check_all(&data);
+}
+
+#[test]
+fn grow_by_realloc() {
+    let mut ops = vec![Op::Alloc { size: 8, align: 8 }];
+    ops.extend((1..64).map(|i| Op::Realloc { index: 0, new_size: 8 + i * 64 }));
+    ops.extend((1..64).rev().map(|i| Op::Realloc { index: 0, new_size: i * 16 }));
+    Checker::<Locked<FixedSizeBlockAllocator>>::new().run(ops.iter().copied());
+    Checker::<check::FirstFit>::new().run(ops.iter().copied());
+    Checker::<check::BestFit>::new().run(ops.iter().copied());
+    Checker::<Locked<TlsfAllocator>>::new().run(ops.iter().copied());
+}
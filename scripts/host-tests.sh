@@ -0,0 +1,14 @@
+#!/bin/sh
+# Runs the allocator property tests on the host. With `fuzz`, feeds random
+# input to the fuzz target until interrupted instead.
+set -e
+cd "$(dirname "$0")/../host-tests"
+
+target=$(rustc -vV | sed -n 's/host: //p')
+if [ "$1" = fuzz ]; then
+    while head -c 4096 /dev/urandom > target/fuzz-input; do
+        cargo run -q --release --target "$target" --bin fuzz_allocators < target/fuzz-input
+    done
+else
+    cargo test --target "$target" "$@"
+fi
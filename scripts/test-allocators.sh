@@ -0,0 +1,17 @@
+#!/bin/sh
+# Runs the heap allocation tests once for every heap allocator. Extra
+# arguments are passed on to cargo.
+set -e
+cd "$(dirname "$0")/.."
+
+for allocator in fixed-size-block bump linked-list upstream-linked-list tlsf; do
+    echo "heap_allocation with the $allocator allocator"
+    if [ "$allocator" = fixed-size-block ]; then
+        cargo test --test heap_allocation "$@"
+    else
+        cargo test --test heap_allocation --features "$allocator" "$@"
+    fi
+done
+
+echo "heap_allocation with the allocation tracker"
+cargo test --test heap_allocation --features alloc-tracker "$@"
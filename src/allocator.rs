@@ -1,29 +1,126 @@
-pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// Size of the virtual area reserved for the heap, the heap never grows beyond it.
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+/// Minimum number of bytes the heap grows by at once.
+const HEAP_GROW_STEP: usize = 64 * 1024;
 pub mod bump;
 pub mod linked_list;
 pub mod fixed_size_block;
+pub mod slab;
+pub mod debug;
+pub mod upstream;
+pub mod tlsf;
+pub mod tracker;
+mod common;
 
+pub use common::{Growable, HeapStatistics, HeapStats, Locked, LockedGuard};
+use common::{align_up, realloc_by_copy, Usage};
 
-use fixed_size_block::FixedSizeBlockAllocator;
+#[cfg(any(
+    all(feature = "bump", feature = "linked-list"),
+    all(feature = "bump", feature = "upstream-linked-list"),
+    all(feature = "bump", feature = "tlsf"),
+    all(feature = "linked-list", feature = "upstream-linked-list"),
+    all(feature = "linked-list", feature = "tlsf"),
+    all(feature = "upstream-linked-list", feature = "tlsf"),
+))]
+compile_error!("at most one of the allocator features can be enabled");
 
+/// The allocator of the kernel heap, selected with the `bump`, `linked-list`,
+/// `upstream-linked-list` or `tlsf` feature. The fixed-size block allocator is
+/// used if none of them is enabled.
+#[cfg(feature = "bump")]
+pub type KernelAllocator = bump::BumpAllocator;
+#[cfg(feature = "linked-list")]
+pub type KernelAllocator = linked_list::LinkedListAllocator;
+#[cfg(feature = "upstream-linked-list")]
+pub type KernelAllocator = upstream::UpstreamHeap;
+#[cfg(feature = "tlsf")]
+pub type KernelAllocator = tlsf::TlsfAllocator;
+#[cfg(not(any(
+    feature = "bump",
+    feature = "linked-list",
+    feature = "upstream-linked-list",
+    feature = "tlsf",
+)))]
+pub type KernelAllocator = fixed_size_block::FixedSizeBlockAllocator;
+
+#[cfg_attr(not(any(feature = "debug-heap", feature = "alloc-tracker")), global_allocator)]
+static ALLOCATOR: GrowableHeap<KernelAllocator> = GrowableHeap::new(KernelAllocator::new());
+
+#[cfg(feature = "debug-heap")]
+#[cfg_attr(not(feature = "alloc-tracker"), global_allocator)]
+static DEBUG_HEAP: debug::DebugHeap<GrowableHeap<KernelAllocator>> =
+    debug::DebugHeap::new(&ALLOCATOR);
+
+#[cfg(all(feature = "alloc-tracker", feature = "debug-heap"))]
 #[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(
-    FixedSizeBlockAllocator::new());
+static TRACKER: tracker::Tracker<debug::DebugHeap<GrowableHeap<KernelAllocator>>> =
+    tracker::Tracker::new(&DEBUG_HEAP);
 
+#[cfg(all(feature = "alloc-tracker", not(feature = "debug-heap")))]
+#[global_allocator]
+static TRACKER: tracker::Tracker<GrowableHeap<KernelAllocator>> =
+    tracker::Tracker::new(&ALLOCATOR);
+
+use crate::memory::{self, vma::{Backing, Owner, KERNEL_VMAS}};
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use x86_64::{
     structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+        mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, Page, PageSize, PageTableFlags, Size4KiB,
     },
     VirtAddr,
 };
 
+const HEAP_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+/// The mapped part of the heap's virtual area.
+struct HeapArea {
+    start: usize,
+    size: usize,
+}
+
+/// Locked like the allocators, so that an interrupt handler that grows the
+/// heap never finds the area locked by the code it interrupted.
+static HEAP_AREA: Locked<HeapArea> = Locked::new(HeapArea { start: 0, size: 0 });
+static HEAP_LIMIT: AtomicUsize = AtomicUsize::new(HEAP_MAX_SIZE);
+
+/// Sets the size the heap may grow to. The limit is capped at `HEAP_MAX_SIZE`
+/// and never shrinks the heap below its current size.
+pub fn set_heap_limit(limit: usize) {
+    // hold the area, so that the heap can't grow past the limit meanwhile
+    let area = HEAP_AREA.lock();
+    HEAP_LIMIT.store(limit.max(area.size).min(HEAP_MAX_SIZE), Ordering::Relaxed);
+}
+
+/// Returns the size the heap may grow to.
+pub fn heap_limit() -> usize {
+    HEAP_LIMIT.load(Ordering::Relaxed)
+}
+
+/// Returns the number of bytes currently mapped for the heap.
+pub fn heap_size() -> usize {
+    HEAP_AREA.lock().size
+}
+
+/// Reserves a virtual area for the heap, maps its first `HEAP_SIZE` bytes and
+/// initializes the global allocator with them.
+///
+/// The heap grows into the rest of the area once `memory::install` was called.
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> Result<(), MapToError<Size4KiB>> {
+    let area_size = HEAP_MAX_SIZE as u64;
+    let heap_start = KERNEL_VMAS
+        .lock()
+        .reserve(area_size, Size4KiB::SIZE, Owner::Heap, HEAP_FLAGS, Backing::Eager)
+        .expect("failed to reserve virtual memory for the heap");
+
     let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
         let heap_end = heap_start + HEAP_SIZE - 1u64;
         let heap_start_page = Page::containing_address(heap_start);
         let heap_end_page = Page::containing_address(heap_end);
@@ -34,38 +131,151 @@ pub fn init_heap(
         let frame = frame_allocator
             .allocate_frame()
             .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
         unsafe {
-            mapper.map_to(page, frame, flags, frame_allocator)?.flush()
+            mapper.map_to(page, frame, HEAP_FLAGS, frame_allocator)?.flush()
         };
     }
 
+    *HEAP_AREA.lock() = HeapArea { start: heap_start.as_u64() as usize, size: HEAP_SIZE };
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.lock().init(heap_start.as_u64() as usize, HEAP_SIZE);
     }
 
     Ok(())
 }
 
+/// Maps at least `min_size` more bytes directly after the end of the heap.
+///
+/// Returns the number of bytes that were added, which can be less than
+/// `min_size` if the system runs out of frames. Returns `None` if nothing
+/// could be added because the heap limit is reached or the kernel memory is
+/// not installed (or locked by the allocating code itself).
+fn grow_heap(min_size: usize) -> Option<usize> {
+    let mut area = HEAP_AREA.lock();
+    let limit = HEAP_LIMIT.load(Ordering::Relaxed);
+    let size = align_up(min_size.max(HEAP_GROW_STEP), Size4KiB::SIZE as usize)
+        .min(limit.saturating_sub(area.size));
+    if size < min_size {
+        return None;
+    }
+
+    let mut memory = memory::KERNEL_MEMORY.try_lock()?;
+    let memory::KernelMemory { mapper, frame_allocator } = memory.as_mut()?;
+    let start = VirtAddr::new((area.start + area.size) as u64);
+    let page_range = Page::range(
+        Page::<Size4KiB>::containing_address(start),
+        Page::containing_address(start + size as u64),
+    );
+    let mut added = 0;
+    for page in page_range {
+        let Some(frame) = frame_allocator.allocate_frame() else { break };
+        match unsafe { mapper.map_to(page, frame, HEAP_FLAGS, frame_allocator) } {
+            Ok(flush) => flush.flush(),
+            Err(_) => {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                break;
+            }
+        }
+        added += Size4KiB::SIZE as usize;
+    }
+    area.size += added;
+    (added > 0).then_some(added)
+}
+
+/// Checks the red zones of all live allocations of the global allocator.
+///
+/// Returns the number of live allocations.
+#[cfg(feature = "debug-heap")]
+pub fn check_heap() -> Result<usize, debug::HeapError> {
+    DEBUG_HEAP.check()
+}
+
+/// Remembers the live allocations of the global allocator, for finding leaks
+/// with `leaks_since`.
+#[cfg(feature = "alloc-tracker")]
+pub fn snapshot() -> tracker::Snapshot {
+    TRACKER.snapshot()
+}
+
+/// Returns the allocations that were made since the snapshot and are still
+/// live, grouped by call site.
+#[cfg(feature = "alloc-tracker")]
+pub fn leaks_since(snapshot: &tracker::Snapshot) -> tracker::LeakReport {
+    TRACKER.leaks_since(snapshot)
+}
+
+/// Prints the call sites that leaked the most memory since the snapshot to
+/// the serial port.
+#[cfg(feature = "alloc-tracker")]
+pub fn print_leaks(snapshot: &tracker::Snapshot) {
+    crate::serial_println!("{}", leaks_since(snapshot));
+}
+
+/// Runs `f` and panics with a leak report if any allocation it made is still
+/// live afterwards.
+#[cfg(feature = "alloc-tracker")]
+pub fn assert_no_leaks(f: impl FnOnce()) {
+    let snapshot = snapshot();
+    f();
+    let leaks = leaks_since(&snapshot);
+    assert!(leaks.is_empty(), "{}", leaks);
+}
 
-/// A wrapper around spin::Mutex to permit trait implementations.
-pub struct Locked<A> {
-    inner: spin::Mutex<A>,
+/// Returns the usage statistics of the global allocator.
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
 }
 
-impl<A> Locked<A> {
+/// Wraps an allocator so that the heap is grown instead of failing when the
+/// allocator runs out of memory.
+pub struct GrowableHeap<A> {
+    allocator: Locked<A>,
+}
+
+impl<A> GrowableHeap<A> {
     pub const fn new(inner: A) -> Self {
-        Locked {
-            inner: spin::Mutex::new(inner),
+        GrowableHeap {
+            allocator: Locked::new(inner),
         }
     }
 
-    pub fn lock(&self) -> spin::MutexGuard<'_,A> {
-        self.inner.lock()
+    pub fn lock(&self) -> LockedGuard<'_, A> {
+        self.allocator.lock()
     }
 }
 
-/// Requires that `align` is a power of two.
-fn align_up(addr: usize, align: usize) -> usize {
-    (addr + align - 1) & !(align - 1)
-}
\ No newline at end of file
+unsafe impl<A: Growable> GlobalAlloc for GrowableHeap<A>
+where
+    Locked<A>: GlobalAlloc,
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        loop {
+            let ptr = unsafe { self.allocator.alloc(layout) };
+            if !ptr.is_null() {
+                return ptr;
+            }
+            // leave room for aligning the allocation within the new memory
+            match grow_heap(layout.size() + layout.align()) {
+                Some(added) => unsafe { self.lock().extend(added) },
+                None => return ptr,
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.allocator.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        loop {
+            let new_ptr = unsafe { self.allocator.realloc(ptr, layout, new_size) };
+            if !new_ptr.is_null() {
+                return new_ptr;
+            }
+            match grow_heap(new_size + layout.align()) {
+                Some(added) => unsafe { self.lock().extend(added) },
+                None => return new_ptr,
+            }
+        }
+    }
+}
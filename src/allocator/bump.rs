@@ -5,6 +5,7 @@ pub struct BumpAllocator {
     heap_end: usize,
     next: usize,
     allocations: usize,
+    usage: Usage,
 }
 
 impl BumpAllocator {
@@ -15,6 +16,7 @@ impl BumpAllocator {
             heap_end: 0,
             next: 0,
             allocations: 0,
+            usage: Usage::new(),
         }
     }
 
@@ -29,7 +31,26 @@ impl BumpAllocator {
     }
 }
 
-use super::{align_up, Locked};
+impl HeapStatistics for BumpAllocator {
+    fn stats(&mut self) -> HeapStats {
+        HeapStats {
+            heap_size: self.heap_end - self.heap_start,
+            // only the space after `next` is reused before all allocations
+            // are freed
+            free_bytes: self.heap_end - self.next,
+            largest_free_block: self.heap_end - self.next,
+            ..self.usage.stats()
+        }
+    }
+}
+
+impl super::Growable for BumpAllocator {
+    unsafe fn extend(&mut self, by: usize) {
+        self.heap_end += by;
+    }
+}
+
+use super::{align_up, realloc_by_copy, HeapStatistics, HeapStats, Locked, Usage};
 use core::ptr;
 
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
@@ -47,17 +68,40 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
         } else {
             bump.next = alloc_end;
             bump.allocations += 1;
+            bump.usage.record_alloc(layout.size());
             alloc_start as *mut u8
         }
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+    unsafe fn dealloc(&self, _ptr: *mut u8, layout: Layout) {
         let mut bump = self.lock(); // get a mutable reference
 
         bump.allocations -= 1;
+        bump.usage.record_dealloc(layout.size());
         if bump.allocations == 0 {
             bump.next = bump.heap_start;
         }
     }
-}
 
+    /// Resizes the last allocation in place by moving `next`. Other
+    /// allocations are only shrunk in place, since their memory is not reused
+    /// before all allocations are freed anyway.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        {
+            let mut bump = self.lock();
+            let start = ptr as usize;
+            let is_last = start + layout.size() == bump.next;
+            let in_place = if is_last && start + new_size <= bump.heap_end {
+                bump.next = start + new_size;
+                true
+            } else {
+                new_size <= layout.size()
+            };
+            if in_place {
+                bump.usage.record_realloc(layout.size(), new_size);
+                return ptr;
+            }
+        }
+        unsafe { realloc_by_copy(self, ptr, layout, new_size) }
+    }
+}
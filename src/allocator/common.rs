@@ -0,0 +1,215 @@
+// The parts of the heap allocators that do not depend on the kernel, so that
+// the allocators can also be built for the host by `host-tests`.
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::{
+    fmt,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+#[cfg(target_os = "none")]
+use x86_64::instructions::interrupts;
+
+/// Usage statistics of a heap allocator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Bytes of memory managed by the allocator.
+    pub heap_size: usize,
+    /// Bytes currently allocated, as requested by the layouts.
+    pub allocated_bytes: usize,
+    pub live_allocations: usize,
+    /// The highest value `allocated_bytes` reached so far.
+    pub peak_allocated_bytes: usize,
+    /// Bytes in the free regions of the heap. For the fixed-size block
+    /// allocator, these are the free bytes of the fallback heap.
+    pub free_bytes: usize,
+    /// Size of the largest allocation that currently fits into the free
+    /// regions without growing the heap.
+    pub largest_free_block: usize,
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "heap {} B: {} B in {} allocations (peak {} B), {} B free, largest free block {} B",
+            self.heap_size,
+            self.allocated_bytes,
+            self.live_allocations,
+            self.peak_allocated_bytes,
+            self.free_bytes,
+            self.largest_free_block
+        )
+    }
+}
+
+/// Allocators that report their usage.
+pub trait HeapStatistics {
+    /// Returns the current usage statistics.
+    ///
+    /// This takes `&mut self` because some allocators have to probe their
+    /// free regions with allocations that are freed again immediately.
+    fn stats(&mut self) -> HeapStats;
+}
+
+/// The counters for `HeapStats` that every allocator keeps.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Usage {
+    allocated_bytes: usize,
+    live_allocations: usize,
+    peak_allocated_bytes: usize,
+}
+
+impl Usage {
+    pub(super) const fn new() -> Self {
+        Usage { allocated_bytes: 0, live_allocations: 0, peak_allocated_bytes: 0 }
+    }
+
+    pub(super) fn record_alloc(&mut self, size: usize) {
+        self.allocated_bytes += size;
+        self.live_allocations += 1;
+        self.peak_allocated_bytes = self.peak_allocated_bytes.max(self.allocated_bytes);
+    }
+
+    pub(super) fn record_dealloc(&mut self, size: usize) {
+        self.allocated_bytes -= size;
+        self.live_allocations -= 1;
+    }
+
+    pub(super) fn record_realloc(&mut self, old_size: usize, new_size: usize) {
+        self.allocated_bytes = self.allocated_bytes - old_size + new_size;
+        self.peak_allocated_bytes = self.peak_allocated_bytes.max(self.allocated_bytes);
+    }
+
+    /// Returns stats with the counters filled in and all other fields zero.
+    pub(super) fn stats(&self) -> HeapStats {
+        HeapStats {
+            allocated_bytes: self.allocated_bytes,
+            live_allocations: self.live_allocations,
+            peak_allocated_bytes: self.peak_allocated_bytes,
+            ..HeapStats::default()
+        }
+    }
+}
+
+/// Allocators whose heap can grow at its end.
+pub trait Growable {
+    /// Adds the `by` bytes directly after the current end of the heap.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// memory is mapped and unused.
+    unsafe fn extend(&mut self, by: usize);
+}
+
+/// A wrapper around spin::Mutex to permit trait implementations.
+///
+/// Interrupts are disabled while the lock is held, so that interrupt handlers
+/// can allocate. The kernel runs on a single CPU, so a handler never finds
+/// the lock taken by the code it interrupted.
+pub struct Locked<A> {
+    inner: spin::Mutex<A>,
+}
+
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Self {
+        Locked {
+            inner: spin::Mutex::new(inner),
+        }
+    }
+
+    pub fn lock(&self) -> LockedGuard<'_, A> {
+        let interrupts_enabled = disable_interrupts();
+        LockedGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            interrupts_enabled,
+        }
+    }
+}
+
+/// The guard of a `Locked`, which restores the interrupt flag after the lock
+/// is released.
+pub struct LockedGuard<'a, A> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, A>>,
+    interrupts_enabled: bool,
+}
+
+impl<A> Deref for LockedGuard<'_, A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.guard
+    }
+}
+
+impl<A> DerefMut for LockedGuard<'_, A> {
+    fn deref_mut(&mut self) -> &mut A {
+        &mut self.guard
+    }
+}
+
+impl<A> Drop for LockedGuard<'_, A> {
+    fn drop(&mut self) {
+        // unlock before enabling interrupts, which a field drop would not do
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.interrupts_enabled {
+            enable_interrupts();
+        }
+    }
+}
+
+/// Disables interrupts and returns whether they were enabled before.
+#[cfg(target_os = "none")]
+fn disable_interrupts() -> bool {
+    let enabled = interrupts::are_enabled();
+    interrupts::disable();
+    enabled
+}
+
+#[cfg(target_os = "none")]
+fn enable_interrupts() {
+    interrupts::enable();
+}
+
+/// Host builds of the allocators, as in `host-tests`, run without interrupts.
+#[cfg(not(target_os = "none"))]
+fn disable_interrupts() -> bool {
+    false
+}
+
+#[cfg(not(target_os = "none"))]
+fn enable_interrupts() {}
+
+impl<A: HeapStatistics> Locked<A> {
+    pub fn stats(&self) -> HeapStats {
+        self.lock().stats()
+    }
+}
+
+/// Moves an allocation into a new block of `new_size` bytes, like the default
+/// `GlobalAlloc::realloc`. The allocators use this when they cannot resize
+/// the allocation in place, so it must be called without holding their lock.
+///
+/// This function is unsafe because it has the same requirements as
+/// `GlobalAlloc::realloc`.
+pub(super) unsafe fn realloc_by_copy(
+    allocator: &impl GlobalAlloc,
+    ptr: *mut u8,
+    layout: Layout,
+    new_size: usize,
+) -> *mut u8 {
+    let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+    let new_ptr = unsafe { allocator.alloc(new_layout) };
+    if !new_ptr.is_null() {
+        unsafe {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            allocator.dealloc(ptr, layout);
+        }
+    }
+    new_ptr
+}
+
+/// Requires that `align` is a power of two.
+pub(super) fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
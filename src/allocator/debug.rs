@@ -0,0 +1,288 @@
+use super::Locked;
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::{fmt, mem, ptr, slice};
+
+/// Size of the guard areas before and after every allocation.
+const RED_ZONE: usize = 16;
+
+/// Fill patterns for fresh allocations, freed memory and the red zones.
+const ALLOC_POISON: u8 = 0xcd;
+const FREE_POISON: u8 = 0xdd;
+const GUARD: u8 = 0xfd;
+
+/// Values of `Header::magic` for live and freed allocations.
+const ALLOCATED: u64 = 0xa110_ca7e_a110_ca7e;
+const FREED: u64 = 0xdead_beef_dead_beef;
+
+/// Header directly in front of the red zone before every allocation.
+///
+/// The magic value is the last field, so that it survives the free list node
+/// that the wrapped allocator writes to the start of a freed block.
+#[repr(C)]
+struct Header {
+    next: *mut Header,
+    prev: *mut Header,
+    size: usize,
+    align: usize,
+    magic: u64,
+}
+
+const HEADER_SIZE: usize = mem::size_of::<Header>();
+
+/// The live allocations, for walking the heap.
+struct LiveList {
+    head: *mut Header,
+    len: usize,
+}
+
+unsafe impl Send for LiveList {}
+
+impl LiveList {
+    /// Checks the allocation and removes it from the live list.
+    unsafe fn unlink(&mut self, ptr: *mut u8, layout: Layout) -> Result<*mut Header, HeapError> {
+        let header = header_of(ptr);
+        unsafe {
+            match (*header).magic {
+                ALLOCATED => {}
+                FREED => return Err(HeapError::DoubleFree(ptr as usize)),
+                _ => return Err(HeapError::BadHeader(ptr as usize)),
+            }
+            let allocated = Layout::from_size_align_unchecked((*header).size, (*header).align);
+            if allocated != layout {
+                let addr = ptr as usize;
+                return Err(HeapError::LayoutMismatch { addr, allocated, freed: layout });
+            }
+            check_allocation(header)?;
+
+            let (next, prev) = ((*header).next, (*header).prev);
+            if prev.is_null() {
+                self.head = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+            self.len -= 1;
+        }
+        Ok(header)
+    }
+
+    /// Writes the header and the red zones of the allocation at `ptr` and
+    /// adds it to the live list.
+    unsafe fn link(&mut self, ptr: *mut u8, layout: Layout) {
+        unsafe {
+            ptr.sub(RED_ZONE).write_bytes(GUARD, RED_ZONE);
+            ptr.add(layout.size()).write_bytes(GUARD, RED_ZONE);
+            let header = header_of(ptr);
+            header.write(Header {
+                next: self.head,
+                prev: ptr::null_mut(),
+                size: layout.size(),
+                align: layout.align(),
+                magic: ALLOCATED,
+            });
+            if !self.head.is_null() {
+                (*self.head).prev = header;
+            }
+            self.head = header;
+            self.len += 1;
+        }
+    }
+}
+
+/// An error found by the debug heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapError {
+    /// The allocation at the given address was already freed.
+    DoubleFree(usize),
+    /// `dealloc` was called with a different layout than the allocation.
+    LayoutMismatch { addr: usize, allocated: Layout, freed: Layout },
+    /// The header in front of the given address is overwritten, or the
+    /// address was never returned by the debug heap.
+    BadHeader(usize),
+    /// The red zone before the allocation is overwritten.
+    Underflow(usize),
+    /// The red zone after the allocation is overwritten.
+    Overflow(usize),
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeapError::DoubleFree(addr) => write!(f, "double free of {:#x}", addr),
+            HeapError::LayoutMismatch { addr, allocated, freed } => write!(
+                f,
+                "{:#x} allocated with size {} align {} but freed with size {} align {}",
+                addr,
+                allocated.size(),
+                allocated.align(),
+                freed.size(),
+                freed.align()
+            ),
+            HeapError::BadHeader(addr) => {
+                write!(f, "corrupted header or invalid pointer {:#x}", addr)
+            }
+            HeapError::Underflow(addr) => write!(f, "write before the allocation at {:#x}", addr),
+            HeapError::Overflow(addr) => write!(f, "write after the allocation at {:#x}", addr),
+        }
+    }
+}
+
+/// Wraps an allocator to catch heap corruption early.
+///
+/// Every allocation gets a header and a red zone on both sides, which are
+/// checked when it is freed. Fresh allocations are filled with `0xcd` and
+/// freed memory with `0xdd`, so reads of uninitialized or freed memory are
+/// easy to recognize. Double frees are detected as long as the memory has
+/// not been handed out again.
+pub struct DebugHeap<A: 'static> {
+    inner: &'static A,
+    live: Locked<LiveList>,
+}
+
+impl<A> DebugHeap<A> {
+    pub const fn new(inner: &'static A) -> Self {
+        DebugHeap {
+            inner,
+            live: Locked::new(LiveList { head: ptr::null_mut(), len: 0 }),
+        }
+    }
+
+    /// Checks the headers and red zones of all live allocations.
+    ///
+    /// Returns the number of live allocations.
+    pub fn check(&self) -> Result<usize, HeapError> {
+        let live = self.live.lock();
+        let mut header = live.head;
+        while !header.is_null() {
+            unsafe {
+                check_allocation(header)?;
+                header = (*header).next;
+            }
+        }
+        Ok(live.len)
+    }
+}
+
+impl<A: GlobalAlloc> DebugHeap<A> {
+    /// Frees the allocation like `dealloc`, but returns an error instead of
+    /// panicking if the allocation is invalid or corrupted. The memory is not
+    /// freed in that case.
+    ///
+    /// This function is unsafe because the caller must guarantee that `ptr`
+    /// was returned by this heap.
+    pub unsafe fn try_dealloc(&self, ptr: *mut u8, layout: Layout) -> Result<(), HeapError> {
+        unsafe {
+            let header = self.live.lock().unlink(ptr, layout)?;
+            (*header).magic = FREED;
+            ptr.sub(RED_ZONE).write_bytes(FREE_POISON, RED_ZONE + layout.size() + RED_ZONE);
+            let block = ptr.sub(front_size(layout.align()));
+            self.inner.dealloc(block, inner_layout(layout).unwrap());
+        }
+        Ok(())
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for DebugHeap<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(inner_layout) = inner_layout(layout) else {
+            return ptr::null_mut();
+        };
+        let block = unsafe { self.inner.alloc(inner_layout) };
+        if block.is_null() {
+            return block;
+        }
+        unsafe {
+            let ptr = block.add(front_size(layout.align()));
+            ptr.write_bytes(ALLOC_POISON, layout.size());
+            self.live.lock().link(ptr, layout);
+            ptr
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Err(err) = unsafe { self.try_dealloc(ptr, layout) } {
+            panic!("debug heap: {}", err);
+        }
+    }
+
+    /// Resizes the block with the wrapped allocator, so that it can still
+    /// grow or shrink in place. Added bytes are poisoned like fresh memory,
+    /// and the old allocation like freed memory if the block moved.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+            return ptr::null_mut();
+        };
+        let Some(new_inner_layout) = inner_layout(new_layout) else {
+            return ptr::null_mut();
+        };
+        // the lock keeps interrupt handlers from getting the old block before
+        // it is poisoned
+        let mut live = self.live.lock();
+        if let Err(err) = unsafe { live.unlink(ptr, layout) } {
+            drop(live);
+            panic!("debug heap: {}", err);
+        }
+        unsafe {
+            let front = front_size(layout.align());
+            let block = ptr.sub(front);
+            let new_block =
+                self.inner.realloc(block, inner_layout(layout).unwrap(), new_inner_layout.size());
+            if new_block.is_null() {
+                live.link(ptr, layout);
+                return new_block;
+            }
+            if new_block != block {
+                // the wrapped allocator freed the old block, poison it like
+                // `try_dealloc` does; its free list node only covers the
+                // start of the header
+                (*header_of(ptr)).magic = FREED;
+                ptr.sub(RED_ZONE).write_bytes(FREE_POISON, RED_ZONE + layout.size() + RED_ZONE);
+            }
+            let new_ptr = new_block.add(front);
+            if new_size > layout.size() {
+                new_ptr.add(layout.size()).write_bytes(ALLOC_POISON, new_size - layout.size());
+            }
+            live.link(new_ptr, new_layout);
+            new_ptr
+        }
+    }
+}
+
+/// Returns the offset of the allocation from the start of its block, which
+/// leaves room for the header and the front red zone.
+fn front_size(align: usize) -> usize {
+    (HEADER_SIZE + RED_ZONE).next_multiple_of(align)
+}
+
+/// Returns the layout of the block that holds the given allocation.
+fn inner_layout(layout: Layout) -> Option<Layout> {
+    let size = front_size(layout.align())
+        .checked_add(layout.size())?
+        .checked_add(RED_ZONE)?;
+    Layout::from_size_align(size, layout.align().max(mem::align_of::<Header>())).ok()
+}
+
+fn header_of(ptr: *mut u8) -> *mut Header {
+    ptr.wrapping_sub(RED_ZONE + HEADER_SIZE).cast()
+}
+
+/// Checks the magic value and both red zones of a live allocation.
+unsafe fn check_allocation(header: *mut Header) -> Result<(), HeapError> {
+    let ptr = unsafe { header.cast::<u8>().add(HEADER_SIZE + RED_ZONE) };
+    let addr = ptr as usize;
+    let (size, magic) = unsafe { ((*header).size, (*header).magic) };
+    if magic != ALLOCATED {
+        return Err(HeapError::BadHeader(addr));
+    }
+    let front = unsafe { slice::from_raw_parts(ptr.sub(RED_ZONE), RED_ZONE) };
+    if front.iter().any(|&b| b != GUARD) {
+        return Err(HeapError::Underflow(addr));
+    }
+    let rear = unsafe { slice::from_raw_parts(ptr.add(size), RED_ZONE) };
+    if rear.iter().any(|&b| b != GUARD) {
+        return Err(HeapError::Overflow(addr));
+    }
+    Ok(())
+}
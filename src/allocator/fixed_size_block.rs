@@ -11,18 +11,59 @@ struct ListNode {
 /// the block alignment (alignments must be always powers of 2).
 const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
+/// Minimum size of the pages that are carved into blocks.
+const PAGE_SIZE: usize = 4096;
+
+/// The pages of the larger block sizes span several 4 KiB pages, so that
+/// they hold at least this many blocks and the page header covers at most
+/// one of them.
+const MIN_BLOCKS_PER_PAGE: usize = 8;
+
+/// Header at the start of every page that is carved into blocks of one size.
+///
+/// The first blocks of the page are covered by the header, the remaining
+/// ones are kept in the page's own free list. This way a page whose blocks
+/// are all free again can be given back to the fallback allocator.
+struct BlockPage {
+    next: *mut BlockPage,
+    prev: *mut BlockPage,
+    free_list: Option<&'static mut ListNode>,
+    free_blocks: usize,
+}
+
+struct SizeClass {
+    /// Pages with at least one free block.
+    available: *mut BlockPage,
+    /// Number of pages of this size, including full ones.
+    pages: usize,
+}
+
 pub struct FixedSizeBlockAllocator {
-    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    classes: [SizeClass; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
+    usage: Usage,
+}
+
+unsafe impl Send for FixedSizeBlockAllocator {}
+
+/// The pages and free blocks of one block size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeClassStats {
+    pub block_size: usize,
+    /// Size of the pages of this class in bytes.
+    pub page_size: usize,
+    pub pages: usize,
+    pub free_blocks: usize,
 }
 
 impl FixedSizeBlockAllocator {
     /// Creates an empty FixedSizeBlockAllocator.
     pub const fn new() -> Self {
-        const EMPTY: Option<&'static mut ListNode> = None;
+        const EMPTY: SizeClass = SizeClass { available: ptr::null_mut(), pages: 0 };
         FixedSizeBlockAllocator {
-            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            classes: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            usage: Usage::new(),
         }
     }
 
@@ -36,6 +77,48 @@ impl FixedSizeBlockAllocator {
     }
 }
 
+impl FixedSizeBlockAllocator {
+    /// Returns the number of pages and free blocks of every block size.
+    pub fn size_classes(&self) -> [SizeClassStats; BLOCK_SIZES.len()] {
+        core::array::from_fn(|index| {
+            let mut free_blocks = 0;
+            let mut page = self.classes[index].available;
+            while !page.is_null() {
+                unsafe {
+                    free_blocks += (*page).free_blocks;
+                    page = (*page).next;
+                }
+            }
+            SizeClassStats {
+                block_size: BLOCK_SIZES[index],
+                page_size: page_size(index),
+                pages: self.classes[index].pages,
+                free_blocks,
+            }
+        })
+    }
+}
+
+impl HeapStatistics for FixedSizeBlockAllocator {
+    /// Reports the free regions of the fallback heap. Pages that are carved
+    /// into blocks count as used, their free blocks are reported by
+    /// `size_classes` instead.
+    fn stats(&mut self) -> HeapStats {
+        HeapStats {
+            heap_size: self.fallback_allocator.size(),
+            free_bytes: self.fallback_allocator.free(),
+            largest_free_block: upstream::largest_free_block(&mut self.fallback_allocator),
+            ..self.usage.stats()
+        }
+    }
+}
+
+impl super::Growable for FixedSizeBlockAllocator {
+    unsafe fn extend(&mut self, by: usize) {
+        unsafe { self.fallback_allocator.extend(by) }
+    }
+}
+
 use alloc::alloc::Layout;
 use core::ptr;
 
@@ -47,6 +130,121 @@ impl FixedSizeBlockAllocator {
             Err(_) => ptr::null_mut(),
         }
     }
+
+    /// Takes a block from the given size class, carving a new page into
+    /// blocks if no page has a free block left.
+    fn alloc_block(&mut self, index: usize) -> *mut u8 {
+        let mut page = self.classes[index].available;
+        if page.is_null() {
+            page = self.add_page(index);
+            if page.is_null() {
+                return ptr::null_mut();
+            }
+        }
+        let (node, full) = unsafe {
+            let page = &mut *page;
+            let node = page.free_list.take().expect("available page has no free block");
+            page.free_list = node.next.take();
+            page.free_blocks -= 1;
+            (node as *mut ListNode, page.free_list.is_none())
+        };
+        if full {
+            self.unlink(index, page);
+        }
+        node as *mut u8
+    }
+
+    /// Returns a block to its page.
+    ///
+    /// Pages whose blocks are all free are given back to the fallback
+    /// allocator, except for the last available page of the class, so that
+    /// allocating and freeing a single block does not move a page back and
+    /// forth.
+    fn dealloc_block(&mut self, block: *mut u8, index: usize) {
+        // verify that block has size and alignment required for storing node
+        assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+        assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+        let page = (block as usize & !(page_size(index) - 1)) as *mut BlockPage;
+        let free_blocks = unsafe {
+            let page = &mut *page;
+            let node = block as *mut ListNode;
+            node.write(ListNode { next: page.free_list.take() });
+            page.free_list = Some(&mut *node);
+            page.free_blocks += 1;
+            page.free_blocks
+        };
+        if free_blocks == 1 {
+            self.link(index, page);
+        }
+        let only_page = self.classes[index].available == page && unsafe { (*page).next.is_null() };
+        if free_blocks == blocks_per_page(index) && !only_page {
+            self.unlink(index, page);
+            self.classes[index].pages -= 1;
+            let layout = Layout::from_size_align(page_size(index), page_size(index)).unwrap();
+            unsafe {
+                self.fallback_allocator.deallocate(NonNull::new(page as *mut u8).unwrap(), layout);
+            }
+        }
+    }
+
+    /// Allocates a page from the fallback allocator, splits it into blocks
+    /// of the given size class and makes it available.
+    ///
+    /// Returns a null pointer if the fallback allocator has no page left.
+    fn add_page(&mut self, index: usize) -> *mut BlockPage {
+        let layout = Layout::from_size_align(page_size(index), page_size(index)).unwrap();
+        let page = self.fallback_alloc(layout) as *mut BlockPage;
+        if page.is_null() {
+            return page;
+        }
+        let block_size = BLOCK_SIZES[index];
+        let mut free_list = None;
+        // push in reverse so that blocks are handed out in address order
+        for offset in (first_block_offset(index)..page_size(index)).step_by(block_size).rev() {
+            let node = (page as usize + offset) as *mut ListNode;
+            unsafe {
+                node.write(ListNode { next: free_list.take() });
+                free_list = Some(&mut *node);
+            }
+        }
+        unsafe {
+            page.write(BlockPage {
+                next: ptr::null_mut(),
+                prev: ptr::null_mut(),
+                free_list,
+                free_blocks: blocks_per_page(index),
+            });
+        }
+        self.classes[index].pages += 1;
+        self.link(index, page);
+        page
+    }
+
+    /// Adds the page to the front of the available pages of its class.
+    fn link(&mut self, index: usize, page: *mut BlockPage) {
+        let head = self.classes[index].available;
+        unsafe {
+            (*page).next = head;
+            (*page).prev = ptr::null_mut();
+            if !head.is_null() {
+                (*head).prev = page;
+            }
+        }
+        self.classes[index].available = page;
+    }
+
+    /// Removes the page from the available pages of its class.
+    fn unlink(&mut self, index: usize, page: *mut BlockPage) {
+        let (next, prev) = unsafe { ((*page).next, (*page).prev) };
+        if prev.is_null() {
+            self.classes[index].available = next;
+        } else {
+            unsafe { (*prev).next = next };
+        }
+        if !next.is_null() {
+            unsafe { (*next).prev = prev };
+        }
+    }
 }
 
 /// Choose an appropriate block size for the given layout.
@@ -57,50 +255,42 @@ fn list_index(layout: &Layout) -> Option<usize> {
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
 }
 
-use super::Locked;
+/// Returns the size of the pages of the given size class, which is also
+/// their alignment.
+fn page_size(index: usize) -> usize {
+    (BLOCK_SIZES[index] * MIN_BLOCKS_PER_PAGE).max(PAGE_SIZE)
+}
+
+/// Returns the offset of the first block in a page, behind the page header.
+fn first_block_offset(index: usize) -> usize {
+    mem::size_of::<BlockPage>().next_multiple_of(BLOCK_SIZES[index])
+}
+
+fn blocks_per_page(index: usize) -> usize {
+    (page_size(index) - first_block_offset(index)) / BLOCK_SIZES[index]
+}
+
+use super::{realloc_by_copy, upstream, HeapStatistics, HeapStats, Locked, Usage};
 use alloc::alloc::GlobalAlloc;
 
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
-        match list_index(&layout) {
-            Some(index) => {
-                match allocator.list_heads[index].take() {
-                    Some(node) => {
-                        allocator.list_heads[index] = node.next.take();
-                        node as *mut ListNode as *mut u8
-                    }
-                    None => {
-                        // no block exists in list => allocate new block
-                        let block_size = BLOCK_SIZES[index];
-                        // only works if all block sizes are a power of 2
-                        let block_align = block_size;
-                        let layout = Layout::from_size_align(block_size, block_align)
-                            .unwrap();
-                        allocator.fallback_alloc(layout)
-                    }
-                }
-            }
+        let ptr = match list_index(&layout) {
+            Some(index) => allocator.alloc_block(index),
             None => allocator.fallback_alloc(layout),
+        };
+        if !ptr.is_null() {
+            allocator.usage.record_alloc(layout.size());
         }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
+        allocator.usage.record_dealloc(layout.size());
         match list_index(&layout) {
-            Some(index) => {
-                let new_node = ListNode {
-                    next: allocator.list_heads[index].take(),
-                };
-                // verify that block has size and alignment required for storing node
-                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
-                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
-                let new_node_ptr = ptr as *mut ListNode;
-                unsafe {
-                    new_node_ptr.write(new_node);
-                    allocator.list_heads[index] = Some(&mut *new_node_ptr);
-                }
-            }
+            Some(index) => allocator.dealloc_block(ptr, index),
             None => {
                 let ptr = NonNull::new(ptr).unwrap();
                 unsafe {
@@ -109,5 +299,16 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
             }
         }
     }
-}
 
+    /// Keeps the block if the new size falls into the same size class.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        if let Some(index) = list_index(&layout)
+            && list_index(&new_layout) == Some(index)
+        {
+            self.lock().usage.record_realloc(layout.size(), new_size);
+            return ptr;
+        }
+        unsafe { realloc_by_copy(self, ptr, layout, new_size) }
+    }
+}
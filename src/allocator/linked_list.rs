@@ -17,15 +17,41 @@ impl ListNode {
     }
 }
 
+/// The strategy used to choose among the free regions that fit an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    /// Use the region with the lowest address, which is fast and keeps the
+    /// allocations at the start of the heap.
+    First,
+    /// Use the smallest region, which keeps large regions intact for large
+    /// allocations but always searches the whole list.
+    Best,
+}
+
+/// An allocator that keeps the free regions of the heap in a list sorted by
+/// address. Adjacent free regions are merged when memory is freed.
 pub struct LinkedListAllocator {
     head: ListNode,
+    heap_start: usize,
+    heap_size: usize,
+    fit: Fit,
+    usage: Usage,
 }
 
 impl LinkedListAllocator {
-    /// Creates an empty LinkedListAllocator.
+    /// Creates an empty LinkedListAllocator that uses first fit.
     pub const fn new() -> Self {
+        Self::with_fit(Fit::First)
+    }
+
+    /// Creates an empty LinkedListAllocator with the given fit strategy.
+    pub const fn with_fit(fit: Fit) -> Self {
         Self {
             head: ListNode::new(0),
+            heap_start: 0,
+            heap_size: 0,
+            fit,
+            usage: Usage::new(),
         }
     }
 
@@ -38,6 +64,8 @@ impl LinkedListAllocator {
         unsafe {
             self.add_free_region(heap_start, heap_size);
         }
+        self.heap_start = heap_start;
+        self.heap_size = heap_size;
     }
 
 }
@@ -46,19 +74,42 @@ use super::align_up;
 use core::mem;
 
 impl LinkedListAllocator {
-    /// Adds the given memory region to the front of the list.
+    /// Adds the given memory region to the list, keeping it sorted by
+    /// address and merging the region with adjacent free regions.
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
         // ensure that the freed region is capable of holding ListNode
         assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
         assert!(size >= mem::size_of::<ListNode>());
 
-        // create a new list node and append it at the start of the list
+        // find the last region before the freed one, or the list head
+        let mut current = &mut self.head;
+        while current.next.as_ref().is_some_and(|next| next.start_addr() < addr) {
+            current = current.next.as_mut().unwrap();
+        }
+        // the head is the only node of size 0, it is never merged
+        assert!(current.size == 0 || current.end_addr() <= addr, "freed region is already free");
+
+        let mut size = size;
+        if let Some(next) = current.next.as_ref() {
+            assert!(addr + size <= next.start_addr(), "freed region is already free");
+        }
+        if current.next.as_ref().is_some_and(|next| next.start_addr() == addr + size) {
+            let next = current.next.take().unwrap();
+            size += next.size;
+            current.next = next.next.take();
+        }
+        if current.size != 0 && current.end_addr() == addr {
+            current.size += size;
+            return;
+        }
+
+        // create a new list node and insert it after the previous region
         let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
+        node.next = current.next.take();
         let node_ptr = addr as *mut ListNode;
         unsafe {
             node_ptr.write(node);
-            self.head.next = Some(&mut *node_ptr)
+            current.next = Some(&mut *node_ptr)
         }
     }
 }
@@ -71,24 +122,77 @@ impl LinkedListAllocator {
     fn find_region(&mut self, size: usize, align: usize)
         -> Option<(&'static mut ListNode, usize)>
     {
-        // reference to current list node, updated for each iteration
-        let mut current = &mut self.head;
         // look for a large enough memory region in linked list
-        while let Some(ref mut region) = current.next {
-            if let Ok(alloc_start) = Self::alloc_from_region(&region, size, align) {
-                // region suitable for allocation -> remove node from list
-                let next = region.next.take();
-                let ret = Some((current.next.take().unwrap(), alloc_start));
-                current.next = next;
-                return ret;
-            } else {
-                // region not suitable -> continue with next region
-                current = current.next.as_mut().unwrap();
+        let mut chosen: Option<&ListNode> = None;
+        let mut current = self.head.next.as_deref();
+        while let Some(region) = current {
+            if Self::alloc_from_region(region, size, align).is_ok() {
+                match self.fit {
+                    Fit::First => {
+                        chosen = Some(region);
+                        break;
+                    }
+                    Fit::Best if chosen.is_none_or(|c| region.size < c.size) => {
+                        chosen = Some(region);
+                    }
+                    Fit::Best => {}
+                }
             }
+            current = region.next.as_deref();
         }
+        let target = chosen?.start_addr();
 
-        // no suitable region found
-        None
+        // remove the chosen node from the list
+        let mut current = &mut self.head;
+        while current.next.as_ref().unwrap().start_addr() != target {
+            current = current.next.as_mut().unwrap();
+        }
+        let region = current.next.take().unwrap();
+        current.next = region.next.take();
+        let alloc_start = Self::alloc_from_region(region, size, align).unwrap();
+        Some((region, alloc_start))
+    }
+}
+
+impl LinkedListAllocator {
+    /// Tries to resize the allocated region at `addr` from `old_size` to
+    /// `new_size` bytes without moving it.
+    ///
+    /// Shrinking frees the end of the region, growing takes the needed part
+    /// from the free region that directly follows it. Both only succeed if
+    /// the remaining free part can hold a `ListNode`.
+    fn resize_in_place(&mut self, addr: usize, old_size: usize, new_size: usize) -> bool {
+        let node_size = mem::size_of::<ListNode>();
+        if new_size <= old_size {
+            let excess = old_size - new_size;
+            if excess > 0 && excess < node_size {
+                return false;
+            }
+            if excess > 0 {
+                unsafe { self.add_free_region(addr + new_size, excess) };
+            }
+            return true;
+        }
+
+        let end = addr + old_size;
+        let needed = new_size - old_size;
+        let mut current = &mut self.head;
+        while current.next.as_ref().is_some_and(|next| next.start_addr() < end) {
+            current = current.next.as_mut().unwrap();
+        }
+        let available = match current.next.as_ref() {
+            Some(next) if next.start_addr() == end => next.size,
+            _ => return false,
+        };
+        if available < needed || (available > needed && available - needed < node_size) {
+            return false;
+        }
+        let next = current.next.take().unwrap();
+        current.next = next.next.take();
+        if available > needed {
+            unsafe { self.add_free_region(addr + new_size, available - needed) };
+        }
+        true
     }
 }
 
@@ -100,7 +204,12 @@ impl LinkedListAllocator {
     fn alloc_from_region(region: &ListNode, size: usize, align: usize)
         -> Result<usize, ()>
     {
-        let alloc_start = align_up(region.start_addr(), align);
+        let mut alloc_start = align_up(region.start_addr(), align);
+        let padding = alloc_start - region.start_addr();
+        if padding > 0 && padding < mem::size_of::<ListNode>() {
+            // the padding in front is freed again, so it must hold a ListNode
+            alloc_start = align_up(region.start_addr() + mem::size_of::<ListNode>(), align);
+        }
         let alloc_end = alloc_start.checked_add(size).ok_or(())?;
 
         if alloc_end > region.end_addr() {
@@ -120,7 +229,34 @@ impl LinkedListAllocator {
     }
 }
 
-use super::Locked;
+impl HeapStatistics for LinkedListAllocator {
+    fn stats(&mut self) -> HeapStats {
+        let mut free_bytes = 0;
+        let mut largest_free_block = 0;
+        let mut current = &self.head;
+        while let Some(ref region) = current.next {
+            free_bytes += region.size;
+            largest_free_block = largest_free_block.max(region.size);
+            current = region;
+        }
+        HeapStats {
+            heap_size: self.heap_size,
+            free_bytes,
+            largest_free_block,
+            ..self.usage.stats()
+        }
+    }
+}
+
+impl super::Growable for LinkedListAllocator {
+    unsafe fn extend(&mut self, by: usize) {
+        // merged with the last free region if it reaches the end of the heap
+        unsafe { self.add_free_region(self.heap_start + self.heap_size, by) };
+        self.heap_size += by;
+    }
+}
+
+use super::{realloc_by_copy, HeapStatistics, HeapStats, Locked, Usage};
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::ptr;
 
@@ -138,6 +274,13 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
                     allocator.add_free_region(alloc_end, excess_size);
                 }
             }
+            let padding = alloc_start - region.start_addr();
+            if padding > 0 {
+                unsafe {
+                    allocator.add_free_region(region.start_addr(), padding);
+                }
+            }
+            allocator.usage.record_alloc(layout.size());
             alloc_start as *mut u8
         } else {
             ptr::null_mut()
@@ -148,7 +291,25 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
         // perform layout adjustments
         let (size, _) = LinkedListAllocator::size_align(layout);
 
-        unsafe { self.lock().add_free_region(ptr as usize, size) }
+        let mut allocator = self.lock();
+        allocator.usage.record_dealloc(layout.size());
+        unsafe { allocator.add_free_region(ptr as usize, size) }
+    }
+
+    /// Resizes the allocation in place if the free region that follows it is
+    /// large enough, or if the allocation shrinks.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let (old_size, _) = LinkedListAllocator::size_align(layout);
+        let (adjusted_size, _) = LinkedListAllocator::size_align(new_layout);
+        {
+            let mut allocator = self.lock();
+            if allocator.resize_in_place(ptr as usize, old_size, adjusted_size) {
+                allocator.usage.record_realloc(layout.size(), new_size);
+                return ptr;
+            }
+        }
+        unsafe { realloc_by_copy(self, ptr, layout, new_size) }
     }
 }
 
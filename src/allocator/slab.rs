@@ -0,0 +1,354 @@
+use crate::memory::{KERNEL_MEMORY, KernelMemory};
+use core::{
+    fmt,
+    marker::PhantomData,
+    mem::{align_of, size_of},
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+};
+use spin::Mutex;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame},
+};
+
+/// Every slab is one 4 KiB frame.
+const SLAB_SIZE: usize = 4096;
+
+/// Upper bound for the number of objects per slab, given by the size of the
+/// free bitmap.
+const MAX_OBJECTS: usize = 512;
+
+/// Indexes of the slab lists.
+const EMPTY: usize = 0;
+const PARTIAL: usize = 1;
+const FULL: usize = 2;
+
+/// Header at the start of every slab, followed by the objects.
+struct Slab {
+    next: *mut Slab,
+    prev: *mut Slab,
+    /// A set bit marks a free object. Free objects are not overwritten, so
+    /// that they keep their constructed state.
+    free: [u64; MAX_OBJECTS / 64],
+    in_use: usize,
+}
+
+/// A cache of constructed objects of type `T`.
+///
+/// Objects are carved from slabs, which are whole frames taken from the
+/// frame allocator and accessed through the physical memory mapping. Unlike
+/// the block allocators, objects are packed at their own size instead of
+/// the next power of two. All objects of a new slab are created with the
+/// constructor up front, and freed objects are handed out again as they are,
+/// so callers must return objects in their constructed state. Objects are
+/// only dropped when `shrink` gives their slab back to the frame allocator.
+///
+/// The constructor and destructors must not use the same cache, and the
+/// cache needs `memory::install` to have been called.
+pub struct ObjectCache<T> {
+    name: &'static str,
+    constructor: fn() -> T,
+    /// Offset of the first object within a slab.
+    offset: usize,
+    /// Distance between two objects.
+    stride: usize,
+    objects_per_slab: usize,
+    inner: Mutex<CacheInner>,
+    _marker: PhantomData<T>,
+}
+
+struct CacheInner {
+    lists: [*mut Slab; 3],
+    slabs: [usize; 3],
+    objects_in_use: usize,
+    allocations: u64,
+    frees: u64,
+}
+
+unsafe impl<T: Send> Send for ObjectCache<T> {}
+unsafe impl<T: Send> Sync for ObjectCache<T> {}
+
+impl<T> ObjectCache<T> {
+    /// Creates an empty cache. No memory is allocated before the first
+    /// object is requested.
+    pub const fn new(name: &'static str, constructor: fn() -> T) -> Self {
+        let align = if align_of::<T>() > align_of::<Slab>() {
+            align_of::<T>()
+        } else {
+            align_of::<Slab>()
+        };
+        let offset = size_of::<Slab>().next_multiple_of(align);
+        let size = if size_of::<T>() == 0 { 1 } else { size_of::<T>() };
+        let stride = size.next_multiple_of(align_of::<T>());
+        assert!(offset + stride <= SLAB_SIZE, "object type is too large for a slab");
+        let fitting = (SLAB_SIZE - offset) / stride;
+        let objects_per_slab = if fitting < MAX_OBJECTS { fitting } else { MAX_OBJECTS };
+
+        ObjectCache {
+            name,
+            constructor,
+            offset,
+            stride,
+            objects_per_slab,
+            inner: Mutex::new(CacheInner {
+                lists: [ptr::null_mut(); 3],
+                slabs: [0; 3],
+                objects_in_use: 0,
+                allocations: 0,
+                frees: 0,
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns a constructed object, allocating a new slab if all are full.
+    ///
+    /// Returns `None` if no frame is available for a new slab.
+    pub fn alloc(&self) -> Option<CachedObject<'_, T>> {
+        let mut inner = self.inner.lock();
+        let slab = match inner.lists {
+            [_, partial, _] if !partial.is_null() => partial,
+            [empty, _, _] if !empty.is_null() => empty,
+            _ => self.grow(&mut inner)?,
+        };
+
+        let before = self.list_of(slab);
+        let object = unsafe {
+            let slab_ref = &mut *slab;
+            let (word, bits) = slab_ref.free.iter().enumerate().find(|(_, w)| **w != 0)?;
+            let index = word * 64 + bits.trailing_zeros() as usize;
+            slab_ref.free[word] &= !(1 << (index % 64));
+            slab_ref.in_use += 1;
+            self.object_ptr(slab, index)
+        };
+        self.relink(&mut inner, slab, before);
+        inner.objects_in_use += 1;
+        inner.allocations += 1;
+
+        Some(CachedObject { cache: self, ptr: NonNull::new(object).unwrap() })
+    }
+
+    /// Returns an object to its slab.
+    fn free(&self, object: *mut T) {
+        let mut inner = self.inner.lock();
+        let slab = (object as usize & !(SLAB_SIZE - 1)) as *mut Slab;
+        let index = (object as usize - slab as usize - self.offset) / self.stride;
+        let before = self.list_of(slab);
+        unsafe {
+            let slab_ref = &mut *slab;
+            let bit = 1 << (index % 64);
+            assert!(slab_ref.free[index / 64] & bit == 0, "double free of object {:p}", object);
+            slab_ref.free[index / 64] |= bit;
+            slab_ref.in_use -= 1;
+        }
+        self.relink(&mut inner, slab, before);
+        inner.objects_in_use -= 1;
+        inner.frees += 1;
+    }
+
+    /// Drops the objects of all empty slabs and gives the slabs back to the
+    /// frame allocator.
+    ///
+    /// Returns the number of freed slabs.
+    pub fn shrink(&self) -> usize {
+        let mut inner = self.inner.lock();
+        let mut freed = 0;
+        while !inner.lists[EMPTY].is_null() {
+            let slab = inner.lists[EMPTY];
+            Self::remove(&mut inner, EMPTY, slab);
+            unsafe { self.release_slab(slab) };
+            freed += 1;
+        }
+        freed
+    }
+
+    /// Returns the current statistics of this cache.
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock();
+        CacheStats {
+            name: self.name,
+            object_size: size_of::<T>(),
+            objects_per_slab: self.objects_per_slab,
+            empty_slabs: inner.slabs[EMPTY],
+            partial_slabs: inner.slabs[PARTIAL],
+            full_slabs: inner.slabs[FULL],
+            objects_in_use: inner.objects_in_use,
+            allocations: inner.allocations,
+            frees: inner.frees,
+        }
+    }
+
+    /// Allocates a frame for a new slab, constructs all of its objects and
+    /// adds it to the empty list.
+    fn grow(&self, inner: &mut CacheInner) -> Option<*mut Slab> {
+        let (frame, phys_offset) = {
+            let mut memory = KERNEL_MEMORY.lock();
+            let KernelMemory { mapper, frame_allocator } = memory.as_mut()?;
+            (frame_allocator.allocate_frame()?, mapper.phys_offset())
+        };
+        // the kernel memory is unlocked here, so constructors may allocate
+        let slab: *mut Slab = (phys_offset + frame.start_address().as_u64()).as_mut_ptr();
+        let mut free = [0; MAX_OBJECTS / 64];
+        for index in 0..self.objects_per_slab {
+            unsafe { self.object_ptr(slab, index).write((self.constructor)()) };
+            free[index / 64] |= 1 << (index % 64);
+        }
+        unsafe {
+            slab.write(Slab { next: ptr::null_mut(), prev: ptr::null_mut(), free, in_use: 0 });
+        }
+        Self::push(inner, EMPTY, slab);
+        Some(slab)
+    }
+
+    /// Drops all objects of an unlinked, empty slab and frees its frame.
+    unsafe fn release_slab(&self, slab: *mut Slab) {
+        for index in 0..self.objects_per_slab {
+            unsafe { ptr::drop_in_place(self.object_ptr(slab, index)) };
+        }
+        unsafe { free_frame(slab) };
+    }
+
+    fn object_ptr(&self, slab: *mut Slab, index: usize) -> *mut T {
+        (slab as usize + self.offset + index * self.stride) as *mut T
+    }
+
+    /// Returns the list the given slab belongs in.
+    fn list_of(&self, slab: *mut Slab) -> usize {
+        match unsafe { (*slab).in_use } {
+            0 => EMPTY,
+            n if n == self.objects_per_slab => FULL,
+            _ => PARTIAL,
+        }
+    }
+
+    /// Moves the slab to the matching list if it changed from `before`.
+    fn relink(&self, inner: &mut CacheInner, slab: *mut Slab, before: usize) {
+        let after = self.list_of(slab);
+        if after != before {
+            Self::remove(inner, before, slab);
+            Self::push(inner, after, slab);
+        }
+    }
+
+    fn push(inner: &mut CacheInner, list: usize, slab: *mut Slab) {
+        let head = inner.lists[list];
+        unsafe {
+            (*slab).next = head;
+            (*slab).prev = ptr::null_mut();
+            if !head.is_null() {
+                (*head).prev = slab;
+            }
+        }
+        inner.lists[list] = slab;
+        inner.slabs[list] += 1;
+    }
+
+    fn remove(inner: &mut CacheInner, list: usize, slab: *mut Slab) {
+        let (next, prev) = unsafe { ((*slab).next, (*slab).prev) };
+        if prev.is_null() {
+            inner.lists[list] = next;
+        } else {
+            unsafe { (*prev).next = next };
+        }
+        if !next.is_null() {
+            unsafe { (*next).prev = prev };
+        }
+        inner.slabs[list] -= 1;
+    }
+}
+
+impl<T> Drop for ObjectCache<T> {
+    fn drop(&mut self) {
+        self.shrink();
+        // objects that are still in use can only exist if their handle was
+        // leaked, so they are not dropped
+        let mut inner = self.inner.lock();
+        for list in [PARTIAL, FULL] {
+            while !inner.lists[list].is_null() {
+                let slab = inner.lists[list];
+                Self::remove(&mut inner, list, slab);
+                unsafe { free_frame(slab) };
+            }
+        }
+    }
+}
+
+/// Gives the frame of the given slab back to the frame allocator.
+unsafe fn free_frame(slab: *mut Slab) {
+    let mut memory = KERNEL_MEMORY.lock();
+    let KernelMemory { mapper, frame_allocator } =
+        memory.as_mut().expect("kernel memory not installed");
+    let phys = VirtAddr::from_ptr(slab) - mapper.phys_offset();
+    let frame = PhysFrame::containing_address(PhysAddr::new(phys));
+    unsafe { frame_allocator.deallocate_frame(frame) };
+}
+
+/// An object borrowed from an `ObjectCache`, which returns it on drop.
+pub struct CachedObject<'a, T> {
+    cache: &'a ObjectCache<T>,
+    ptr: NonNull<T>,
+}
+
+impl<T> Deref for CachedObject<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for CachedObject<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for CachedObject<'_, T> {
+    fn drop(&mut self) {
+        self.cache.free(self.ptr.as_ptr());
+    }
+}
+
+/// Statistics of an `ObjectCache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    pub empty_slabs: usize,
+    pub partial_slabs: usize,
+    pub full_slabs: usize,
+    pub objects_in_use: usize,
+    /// Total number of allocations since the cache was created.
+    pub allocations: u64,
+    /// Total number of frees since the cache was created.
+    pub frees: u64,
+}
+
+impl CacheStats {
+    pub fn slabs(&self) -> usize {
+        self.empty_slabs + self.partial_slabs + self.full_slabs
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} B objects, {}/{} in use, {} slabs ({} empty, {} partial, {} full)",
+            self.name,
+            self.object_size,
+            self.objects_in_use,
+            self.slabs() * self.objects_per_slab,
+            self.slabs(),
+            self.empty_slabs,
+            self.partial_slabs,
+            self.full_slabs
+        )
+    }
+}
@@ -0,0 +1,409 @@
+use super::{align_up, realloc_by_copy, HeapStatistics, HeapStats, Locked, Usage};
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::{mem, ptr};
+
+/// Number of second-level lists per first-level class, as a power of two.
+const SL_LOG2: u32 = 4;
+const SL_COUNT: usize = 1 << SL_LOG2;
+
+/// Number of first-level classes, which limits blocks to 2^37 bytes.
+const FL_COUNT: usize = 32;
+
+/// Block sizes and addresses are multiples of this.
+const GRANULE: usize = mem::align_of::<Block>();
+
+/// Blocks below this size all belong to the first first-level class, in
+/// lists that are `GRANULE` bytes apart.
+const SMALL_BLOCK: usize = SL_COUNT * GRANULE;
+const FL_SHIFT: u32 = SMALL_BLOCK.trailing_zeros();
+
+/// The bytes in front of every allocation, which hold the part of the block
+/// header that used blocks keep. The end of the heap costs the same.
+pub const BLOCK_OVERHEAD: usize = 2 * mem::size_of::<usize>();
+
+/// Free blocks also hold the links of their free list.
+const MIN_BLOCK_SIZE: usize = mem::size_of::<Block>();
+
+/// Set in `Block::size` if the block is free.
+const FREE: usize = 1;
+
+/// Header at the start of every block. The blocks cover the heap without
+/// gaps and end with a used block of size 0, so that every block has a
+/// physical successor.
+#[repr(C)]
+struct Block {
+    /// The block directly in front of this one, null for the first block.
+    prev_phys: *mut Block,
+    /// Size of the block including the header, with the `FREE` bit.
+    size: usize,
+    /// The links of the free list, only present in free blocks.
+    next_free: *mut Block,
+    prev_free: *mut Block,
+}
+
+impl Block {
+    fn size(&self) -> usize {
+        self.size & !FREE
+    }
+
+    fn is_free(&self) -> bool {
+        self.size & FREE != 0
+    }
+}
+
+/// Returns the block directly after the given one.
+unsafe fn next_phys(block: *mut Block) -> *mut Block {
+    (block as usize + unsafe { (*block).size() }) as *mut Block
+}
+
+/// Returns the size of the block that holds an allocation of `size` bytes.
+fn block_size(size: usize) -> Option<usize> {
+    let size = size.checked_add(BLOCK_OVERHEAD + GRANULE - 1)? & !(GRANULE - 1);
+    Some(size.max(MIN_BLOCK_SIZE))
+}
+
+/// Returns the first- and second-level index of the list that holds free
+/// blocks of the given size.
+fn mapping(size: usize) -> (usize, usize) {
+    if size < SMALL_BLOCK {
+        (0, size / GRANULE)
+    } else {
+        let log2 = size.ilog2();
+        ((log2 - FL_SHIFT + 1) as usize, (size >> (log2 - SL_LOG2)) - SL_COUNT)
+    }
+}
+
+/// A Two-Level Segregated Fit allocator.
+///
+/// Free blocks are kept in lists by size: the first level splits the sizes
+/// into powers of two, the second level splits each of them into `SL_COUNT`
+/// ranges. Two levels of bitmaps record which lists are non-empty, so a
+/// fitting list is found with two bit scans instead of a search, and freed
+/// blocks are merged with their free neighbours right away through the
+/// headers of the physically adjacent blocks. Allocation and free thus take
+/// bounded time, independent of the number of free blocks.
+///
+/// A request is rounded up to the next list, so that any block of that list
+/// fits. Only if no such block exists, the first block of the request's own
+/// list is tried.
+pub struct TlsfAllocator {
+    /// Bit `fl` is set if any list of first-level class `fl` is non-empty.
+    fl_bitmap: u32,
+    /// Bit `sl` of entry `fl` is set if list `[fl][sl]` is non-empty.
+    sl_bitmaps: [u32; FL_COUNT],
+    lists: [[*mut Block; SL_COUNT]; FL_COUNT],
+    heap_start: usize,
+    heap_size: usize,
+    /// The used block of size 0 at the end of the heap.
+    sentinel: *mut Block,
+    /// Bytes in free blocks that are available for allocations, which
+    /// excludes their headers.
+    free_bytes: usize,
+    usage: Usage,
+}
+
+unsafe impl Send for TlsfAllocator {}
+
+impl Default for TlsfAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TlsfAllocator {
+    /// Creates an empty TlsfAllocator.
+    pub const fn new() -> Self {
+        TlsfAllocator {
+            fl_bitmap: 0,
+            sl_bitmaps: [0; FL_COUNT],
+            lists: [[ptr::null_mut(); SL_COUNT]; FL_COUNT],
+            heap_start: 0,
+            heap_size: 0,
+            sentinel: ptr::null_mut(),
+            free_bytes: 0,
+            usage: Usage::new(),
+        }
+    }
+
+    /// Initialize the allocator with the given heap bounds.
+    ///
+    /// This function is unsafe because the caller must guarantee that the given
+    /// heap bounds are valid and that the heap is unused. This method must be
+    /// called only once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        let start = align_up(heap_start, GRANULE);
+        let end = (heap_start + heap_size) & !(GRANULE - 1);
+        assert!(end >= start + MIN_BLOCK_SIZE + BLOCK_OVERHEAD, "heap too small");
+        self.heap_start = heap_start;
+        self.heap_size = heap_size;
+
+        let block = start as *mut Block;
+        let sentinel = (end - BLOCK_OVERHEAD) as *mut Block;
+        unsafe {
+            (*block).prev_phys = ptr::null_mut();
+            (*block).size = sentinel as usize - start;
+            (*sentinel).prev_phys = block;
+            (*sentinel).size = 0;
+            self.sentinel = sentinel;
+            self.insert_free(block);
+        }
+    }
+
+    /// Adds a free block to its list and marks it free.
+    unsafe fn insert_free(&mut self, block: *mut Block) {
+        let size = unsafe { (*block).size() };
+        let (fl, sl) = mapping(size);
+        let head = self.lists[fl][sl];
+        unsafe {
+            (*block).size = size | FREE;
+            (*block).next_free = head;
+            (*block).prev_free = ptr::null_mut();
+            if !head.is_null() {
+                (*head).prev_free = block;
+            }
+        }
+        self.lists[fl][sl] = block;
+        self.fl_bitmap |= 1 << fl;
+        self.sl_bitmaps[fl] |= 1 << sl;
+        self.free_bytes += size - BLOCK_OVERHEAD;
+    }
+
+    /// Removes a free block from its list and marks it used.
+    unsafe fn remove_free(&mut self, block: *mut Block) {
+        let size = unsafe { (*block).size() };
+        let (fl, sl) = mapping(size);
+        let (next, prev) = unsafe { ((*block).next_free, (*block).prev_free) };
+        if prev.is_null() {
+            self.lists[fl][sl] = next;
+        } else {
+            unsafe { (*prev).next_free = next };
+        }
+        if !next.is_null() {
+            unsafe { (*next).prev_free = prev };
+        }
+        if self.lists[fl][sl].is_null() {
+            self.sl_bitmaps[fl] &= !(1 << sl);
+            if self.sl_bitmaps[fl] == 0 {
+                self.fl_bitmap &= !(1 << fl);
+            }
+        }
+        unsafe { (*block).size = size };
+        self.free_bytes -= size - BLOCK_OVERHEAD;
+    }
+
+    /// Returns the first non-empty list at or after `[fl][sl]`.
+    fn find_suitable(&self, fl: usize, sl: usize) -> Option<(usize, usize)> {
+        let sl_map = self.sl_bitmaps[fl] & (!0 << sl);
+        if sl_map != 0 {
+            return Some((fl, sl_map.trailing_zeros() as usize));
+        }
+        let fl_map = self.fl_bitmap & (!0u32).checked_shl(fl as u32 + 1).unwrap_or(0);
+        if fl_map == 0 {
+            return None;
+        }
+        let fl = fl_map.trailing_zeros() as usize;
+        Some((fl, self.sl_bitmaps[fl].trailing_zeros() as usize))
+    }
+
+    /// Removes a free block of at least `size` bytes from its list.
+    fn take_free_block(&mut self, size: usize) -> Option<*mut Block> {
+        // round up to the next list, so that every block of the found list fits
+        let rounded = if size < SMALL_BLOCK {
+            size
+        } else {
+            size.checked_add((1 << (size.ilog2() - SL_LOG2)) - 1)?
+        };
+        let (fl, sl) = mapping(rounded);
+        let found = if fl < FL_COUNT { self.find_suitable(fl, sl) } else { None };
+        let (fl, sl) = match found {
+            Some(index) => index,
+            None => {
+                // the first block of the request's own list may still fit
+                let (fl, sl) = mapping(size);
+                let head = *self.lists.get(fl)?.get(sl)?;
+                if head.is_null() || unsafe { (*head).size() } < size {
+                    return None;
+                }
+                (fl, sl)
+            }
+        };
+        let block = self.lists[fl][sl];
+        unsafe { self.remove_free(block) };
+        Some(block)
+    }
+
+    /// Frees the given used block, merging it with its free neighbours.
+    unsafe fn free_block(&mut self, block: *mut Block) {
+        let mut block = block;
+        unsafe {
+            let mut size = (*block).size();
+            let next = next_phys(block);
+            if (*next).is_free() {
+                self.remove_free(next);
+                size += (*next).size();
+            }
+            let prev = (*block).prev_phys;
+            if !prev.is_null() && (*prev).is_free() {
+                self.remove_free(prev);
+                size += (*prev).size();
+                block = prev;
+            }
+            (*block).size = size;
+            (*next_phys(block)).prev_phys = block;
+            self.insert_free(block);
+        }
+    }
+
+    /// Shrinks the given used block to `size` bytes and frees the rest, if
+    /// the rest can form a block of its own.
+    unsafe fn split(&mut self, block: *mut Block, size: usize) {
+        let rest = unsafe { (*block).size() } - size;
+        if rest < MIN_BLOCK_SIZE {
+            return;
+        }
+        let remainder = (block as usize + size) as *mut Block;
+        unsafe {
+            (*remainder).prev_phys = block;
+            (*remainder).size = rest;
+            (*block).size = size;
+            (*next_phys(remainder)).prev_phys = remainder;
+            self.free_block(remainder);
+        }
+    }
+
+    /// Allocates a block for the given layout and returns the address of
+    /// the allocation.
+    fn allocate(&mut self, layout: Layout) -> Option<usize> {
+        let size = block_size(layout.size())?;
+        if layout.align() <= GRANULE {
+            let block = self.take_free_block(size)?;
+            unsafe { self.split(block, size) };
+            return Some(block as usize + BLOCK_OVERHEAD);
+        }
+
+        // leave room for a free block in front of the aligned allocation
+        let mut block = self.take_free_block(size.checked_add(layout.align() + MIN_BLOCK_SIZE)?)?;
+        let addr = block as usize + BLOCK_OVERHEAD;
+        let mut gap = align_up(addr, layout.align()) - addr;
+        if gap > 0 && gap < MIN_BLOCK_SIZE {
+            gap = align_up(addr + MIN_BLOCK_SIZE, layout.align()) - addr;
+        }
+        if gap > 0 {
+            let aligned = (block as usize + gap) as *mut Block;
+            unsafe {
+                (*aligned).prev_phys = block;
+                (*aligned).size = (*block).size() - gap;
+                (*next_phys(aligned)).prev_phys = aligned;
+                (*block).size = gap;
+                self.free_block(block);
+            }
+            block = aligned;
+        }
+        unsafe { self.split(block, size) };
+        Some(block as usize + BLOCK_OVERHEAD)
+    }
+
+    /// Tries to resize the allocation at `addr` to `new_size` bytes without
+    /// moving it.
+    ///
+    /// Shrinking frees the end of the block, growing takes the following
+    /// block if it is free and large enough.
+    fn resize_in_place(&mut self, addr: usize, new_size: usize) -> bool {
+        let Some(size) = block_size(new_size) else {
+            return false;
+        };
+        let block = (addr - BLOCK_OVERHEAD) as *mut Block;
+        unsafe {
+            let old_size = (*block).size();
+            if size > old_size {
+                let next = next_phys(block);
+                if !(*next).is_free() || old_size + (*next).size() < size {
+                    return false;
+                }
+                self.remove_free(next);
+                (*block).size = old_size + (*next).size();
+                (*next_phys(block)).prev_phys = block;
+            }
+            self.split(block, size);
+        }
+        true
+    }
+}
+
+impl HeapStatistics for TlsfAllocator {
+    fn stats(&mut self) -> HeapStats {
+        // the largest free block is in the highest non-empty list, whose
+        // blocks are not sorted by size
+        let mut largest_free_block = 0;
+        if self.fl_bitmap != 0 {
+            let fl = self.fl_bitmap.ilog2() as usize;
+            let sl = self.sl_bitmaps[fl].ilog2() as usize;
+            let mut block = self.lists[fl][sl];
+            while !block.is_null() {
+                unsafe {
+                    largest_free_block = largest_free_block.max((*block).size() - BLOCK_OVERHEAD);
+                    block = (*block).next_free;
+                }
+            }
+        }
+        HeapStats {
+            heap_size: self.heap_size,
+            free_bytes: self.free_bytes,
+            largest_free_block,
+            ..self.usage.stats()
+        }
+    }
+}
+
+impl super::Growable for TlsfAllocator {
+    unsafe fn extend(&mut self, by: usize) {
+        self.heap_size += by;
+        let end = (self.heap_start + self.heap_size) & !(GRANULE - 1);
+        let sentinel = (end - BLOCK_OVERHEAD) as *mut Block;
+        let old = self.sentinel;
+        if (sentinel as usize) < old as usize + MIN_BLOCK_SIZE {
+            // too little to form a block, the next extension picks it up
+            return;
+        }
+        // the old end of the heap becomes a block that covers the new memory
+        unsafe {
+            (*old).size = sentinel as usize - old as usize;
+            (*sentinel).prev_phys = old;
+            (*sentinel).size = 0;
+            self.sentinel = sentinel;
+            self.free_block(old);
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<TlsfAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        match allocator.allocate(layout) {
+            Some(addr) => {
+                allocator.usage.record_alloc(layout.size());
+                addr as *mut u8
+            }
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        allocator.usage.record_dealloc(layout.size());
+        unsafe { allocator.free_block(ptr.sub(BLOCK_OVERHEAD).cast()) }
+    }
+
+    /// Resizes the allocation in place if it shrinks, or if the block that
+    /// follows it is free and large enough.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        {
+            let mut allocator = self.lock();
+            if allocator.resize_in_place(ptr as usize, new_size) {
+                allocator.usage.record_realloc(layout.size(), new_size);
+                return ptr;
+            }
+        }
+        unsafe { realloc_by_copy(self, ptr, layout, new_size) }
+    }
+}
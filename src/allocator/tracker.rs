@@ -0,0 +1,284 @@
+use super::Locked;
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::{arch::asm, fmt};
+
+/// Number of allocations the side table can hold, a power of two.
+const CAPACITY: usize = 4096;
+
+/// Allocations beyond this number are not tracked, which keeps the probe
+/// sequences short.
+const MAX_TRACKED: usize = CAPACITY - CAPACITY / 8;
+
+/// Number of return addresses that identify a call site.
+pub const CALL_SITE_DEPTH: usize = 8;
+
+/// Frames larger than this end the frame pointer walk, since they most
+/// likely mean that `rbp` does not point to a frame.
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Number of distinct call sites a leak report can hold.
+const MAX_CALL_SITES: usize = 32;
+
+/// The return addresses of the innermost callers of the allocator.
+pub type CallSite = [usize; CALL_SITE_DEPTH];
+
+#[derive(Clone, Copy)]
+struct Entry {
+    /// The address of the allocation, 0 for an empty slot.
+    addr: usize,
+    size: usize,
+    /// The position of the allocation in the allocation order.
+    seq: u64,
+    call_site: CallSite,
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry { addr: 0, size: 0, seq: 0, call_site: [0; CALL_SITE_DEPTH] };
+}
+
+/// A hash table of the live allocations with linear probing. It is stored
+/// inline, because the tracker can't allocate.
+struct Table {
+    entries: [Entry; CAPACITY],
+    len: usize,
+    next_seq: u64,
+    /// Live allocations that did not fit into the table.
+    untracked: usize,
+}
+
+impl Table {
+    fn home(addr: usize) -> usize {
+        (addr >> 3).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (usize::BITS - CAPACITY.trailing_zeros())
+    }
+
+    fn insert(&mut self, addr: usize, size: usize, call_site: CallSite) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.insert_entry(Entry { addr, size, seq, call_site });
+    }
+
+    fn insert_entry(&mut self, entry: Entry) {
+        if self.len == MAX_TRACKED {
+            self.untracked += 1;
+            return;
+        }
+        let mut slot = Self::home(entry.addr);
+        while self.entries[slot].addr != 0 {
+            slot = (slot + 1) % CAPACITY;
+        }
+        self.entries[slot] = entry;
+        self.len += 1;
+    }
+
+    /// Removes the allocation at `addr` and returns its entry, or `None` if
+    /// it was not tracked.
+    fn remove(&mut self, addr: usize) -> Option<Entry> {
+        let mut slot = Self::home(addr);
+        while self.entries[slot].addr != addr {
+            if self.entries[slot].addr == 0 {
+                // allocated while the table was full
+                self.untracked = self.untracked.saturating_sub(1);
+                return None;
+            }
+            slot = (slot + 1) % CAPACITY;
+        }
+        let removed = self.entries[slot];
+        self.len -= 1;
+
+        // move later entries of the probe sequence into the hole
+        let mut hole = slot;
+        let mut next = slot;
+        loop {
+            next = (next + 1) % CAPACITY;
+            let entry = self.entries[next];
+            if entry.addr == 0 {
+                break;
+            }
+            let home = Self::home(entry.addr);
+            if (next.wrapping_sub(home) % CAPACITY) >= (next.wrapping_sub(hole) % CAPACITY) {
+                self.entries[hole] = entry;
+                hole = next;
+            }
+        }
+        self.entries[hole] = Entry::EMPTY;
+        Some(removed)
+    }
+
+    /// Moves the allocation at `old_addr` to `new_addr` with the new size.
+    /// It keeps its place in the allocation order and its call site.
+    fn rekey(&mut self, old_addr: usize, new_addr: usize, new_size: usize) {
+        match self.remove(old_addr) {
+            Some(entry) => self.insert_entry(Entry { addr: new_addr, size: new_size, ..entry }),
+            None => self.untracked += 1,
+        }
+    }
+
+    fn live(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().filter(|e| e.addr != 0)
+    }
+}
+
+/// A point in time to compare the live allocations against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    seq: u64,
+    live_allocations: usize,
+}
+
+/// The allocations that are still live from one call site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallSiteLeaks {
+    pub call_site: CallSite,
+    pub allocations: usize,
+    pub bytes: usize,
+}
+
+/// The allocations that were made since a snapshot and are still live.
+#[derive(Debug, Clone)]
+pub struct LeakReport {
+    pub allocations: usize,
+    pub bytes: usize,
+    /// Change of the number of live allocations since the snapshot, which
+    /// also counts allocations that are not tracked.
+    pub live_delta: isize,
+    call_sites: [Option<CallSiteLeaks>; MAX_CALL_SITES],
+}
+
+impl LeakReport {
+    /// Returns true if no allocation made since the snapshot is still live.
+    pub fn is_empty(&self) -> bool {
+        self.allocations == 0 && self.live_delta <= 0
+    }
+
+    /// Returns the call sites with the most leaked bytes first.
+    ///
+    /// Only the first `MAX_CALL_SITES` distinct call sites are counted.
+    pub fn call_sites(&self) -> impl Iterator<Item = &CallSiteLeaks> {
+        self.call_sites.iter().flatten()
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} B leaked in {} allocations ({:+} live)",
+            self.bytes, self.allocations, self.live_delta
+        )?;
+        for site in self.call_sites() {
+            write!(f, "\n  {:>8} B in {:>4} allocations at", site.bytes, site.allocations)?;
+            for addr in site.call_site.iter().take_while(|&&a| a != 0) {
+                write!(f, " {:#x}", addr)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wraps an allocator to record the size and call site of every live
+/// allocation.
+///
+/// The call site is found by following the frame pointers, which the target
+/// keeps for all code. Allocations beyond the table capacity are only counted.
+pub struct Tracker<A: 'static> {
+    inner: &'static A,
+    table: Locked<Table>,
+}
+
+impl<A> Tracker<A> {
+    pub const fn new(inner: &'static A) -> Self {
+        Tracker {
+            inner,
+            table: Locked::new(Table {
+                entries: [Entry::EMPTY; CAPACITY],
+                len: 0,
+                next_seq: 0,
+                untracked: 0,
+            }),
+        }
+    }
+
+    /// Remembers the current set of live allocations.
+    pub fn snapshot(&self) -> Snapshot {
+        let table = self.table.lock();
+        Snapshot { seq: table.next_seq, live_allocations: table.len + table.untracked }
+    }
+
+    /// Returns the allocations that were made after the snapshot and are
+    /// still live, grouped by call site.
+    pub fn leaks_since(&self, snapshot: &Snapshot) -> LeakReport {
+        let table = self.table.lock();
+        let mut report = LeakReport {
+            allocations: 0,
+            bytes: 0,
+            live_delta: (table.len + table.untracked) as isize - snapshot.live_allocations as isize,
+            call_sites: [None; MAX_CALL_SITES],
+        };
+        for entry in table.live().filter(|e| e.seq >= snapshot.seq) {
+            report.allocations += 1;
+            report.bytes += entry.size;
+            let slot = report
+                .call_sites
+                .iter_mut()
+                .find(|s| s.is_none_or(|s| s.call_site == entry.call_site));
+            if let Some(slot) = slot {
+                let site = slot.get_or_insert(CallSiteLeaks {
+                    call_site: entry.call_site,
+                    allocations: 0,
+                    bytes: 0,
+                });
+                site.allocations += 1;
+                site.bytes += entry.size;
+            }
+        }
+        drop(table);
+        report.call_sites.sort_unstable_by_key(|s| s.map_or(usize::MAX, |s| usize::MAX - s.bytes));
+        report
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Tracker<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() {
+            self.table.lock().insert(ptr as usize, layout.size(), call_site());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.table.lock().remove(ptr as usize);
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            self.table.lock().rekey(ptr as usize, new_ptr as usize, new_size);
+        }
+        new_ptr
+    }
+}
+
+/// Returns the return addresses of the callers of the calling function by
+/// following the frame pointers.
+#[inline(always)]
+fn call_site() -> CallSite {
+    let mut call_site = [0; CALL_SITE_DEPTH];
+    let mut frame: usize;
+    unsafe { asm!("mov {}, rbp", out(reg) frame, options(nomem, nostack, preserves_flags)) };
+    for addr in call_site.iter_mut() {
+        if frame == 0 || !frame.is_multiple_of(8) {
+            break;
+        }
+        // a frame starts with the caller's frame pointer and return address
+        let (caller_frame, return_addr) =
+            unsafe { (*(frame as *const usize), *((frame + 8) as *const usize)) };
+        *addr = return_addr;
+        if caller_frame <= frame || caller_frame - frame > MAX_FRAME_SIZE {
+            break;
+        }
+        frame = caller_frame;
+    }
+    call_site
+}
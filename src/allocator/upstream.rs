@@ -0,0 +1,96 @@
+use super::{HeapStatistics, HeapStats, Locked, Usage};
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::{mem, ptr::{self, NonNull}};
+use linked_list_allocator::Heap;
+
+/// The allocator of the `linked_list_allocator` crate.
+///
+/// The crate's `LockedHeap` is a `spin::Mutex<Heap>` as well, so the heap is
+/// wrapped in `Locked` instead, which lets it grow and report statistics like
+/// the other allocators.
+pub struct UpstreamHeap {
+    heap: Heap,
+    usage: Usage,
+}
+
+impl Default for UpstreamHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UpstreamHeap {
+    /// Creates an empty UpstreamHeap.
+    pub const fn new() -> Self {
+        UpstreamHeap {
+            heap: Heap::empty(),
+            usage: Usage::new(),
+        }
+    }
+
+    /// Initialize the allocator with the given heap bounds.
+    ///
+    /// This function is unsafe because the caller must guarantee that the given
+    /// heap bounds are valid and that the heap is unused. This method must be
+    /// called only once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        unsafe { self.heap.init(heap_start, heap_size) }
+    }
+}
+
+/// Returns the size of the largest allocation the given heap can currently
+/// satisfy.
+///
+/// The upstream heap does not expose its hole list, so this searches for the
+/// size with allocations that are freed again immediately.
+pub(super) fn largest_free_block(heap: &mut Heap) -> usize {
+    let (mut low, mut high) = (0, heap.free());
+    while low < high {
+        let size = low + (high - low).div_ceil(2);
+        let layout = Layout::from_size_align(size, mem::align_of::<usize>()).unwrap();
+        match heap.allocate_first_fit(layout) {
+            Ok(ptr) => {
+                unsafe { heap.deallocate(ptr, layout) };
+                low = size;
+            }
+            Err(()) => high = size - 1,
+        }
+    }
+    low
+}
+
+impl HeapStatistics for UpstreamHeap {
+    fn stats(&mut self) -> HeapStats {
+        HeapStats {
+            heap_size: self.heap.size(),
+            free_bytes: self.heap.free(),
+            largest_free_block: largest_free_block(&mut self.heap),
+            ..self.usage.stats()
+        }
+    }
+}
+
+impl super::Growable for UpstreamHeap {
+    unsafe fn extend(&mut self, by: usize) {
+        unsafe { self.heap.extend(by) }
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<UpstreamHeap> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        match allocator.heap.allocate_first_fit(layout) {
+            Ok(ptr) => {
+                allocator.usage.record_alloc(layout.size());
+                ptr.as_ptr()
+            }
+            Err(()) => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        allocator.usage.record_dealloc(layout.size());
+        unsafe { allocator.heap.deallocate(NonNull::new(ptr).unwrap(), layout) }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::memory;
 use x86_64::VirtAddr;
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::structures::gdt::SegmentSelector;
@@ -7,26 +8,24 @@ use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor};
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
-    };
-}
+/// Size of the stacks used for interrupt stack table entries.
+const IST_STACK_SIZE: u64 = 4096 * 5;
+
+/// The interrupt stack table entries in use, with the names of their stacks.
+const IST_STACKS: [(u16, &str); 1] = [(DOUBLE_FAULT_IST_INDEX, "double fault")];
+
+/// The task state segment. It is mutable so that `init_ist_stacks` can
+/// replace the initial stacks once memory management is set up; the CPU
+/// reads the IST entries on every interrupt that uses them.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let tss_selector = gdt.add_entry(unsafe {
+            Descriptor::tss_segment_unchecked(&raw const TSS)
+        });
         (gdt, Selectors { code_selector, tss_selector })
     };
 }
@@ -39,10 +38,34 @@ struct Selectors {
 pub fn init() {
     use x86_64::instructions::tables::load_tss;
     use x86_64::instructions::segmentation::{CS, Segment};
-    
+
+    // static stack for the double fault handler until `init_ist_stacks` runs
+    unsafe {
+        const STACK_SIZE: usize = 4096 * 5;
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+        let stack_start = VirtAddr::from_ptr(&raw const STACK);
+        let stack_end = stack_start + STACK_SIZE;
+        TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = stack_end;
+    }
+
     GDT.0.load();
     unsafe {
         CS::set_reg(GDT.1.code_selector);
         load_tss(GDT.1.tss_selector);
     }
-}
\ No newline at end of file
+}
+
+/// Replaces the stacks of all interrupt stack table entries with stacks that
+/// have an unmapped guard page below them.
+///
+/// Called by `memory::install`, since the stacks need the frame allocator.
+pub(crate) fn init_ist_stacks() {
+    for (index, name) in IST_STACKS {
+        let stack = memory::stack::allocate(name, IST_STACK_SIZE)
+            .expect("failed to allocate interrupt stack");
+        unsafe {
+            TSS.interrupt_stack_table[index as usize] = stack.top;
+        }
+    }
+}
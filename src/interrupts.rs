@@ -54,8 +54,18 @@ extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame,er
     {
     use x86_64::registers::control::Cr2;
 
+    let addr = Cr2::read();
+    if crate::memory::demand::handle_page_fault(addr, error_code)
+        || crate::memory::cow::handle_page_fault(addr, error_code)
+    {
+        return;
+    }
+
     println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
+    if let Some(stack) = crate::memory::stack::guard_page_owner(addr) {
+        println!("stack overflow on stack {}", stack);
+    }
+    println!("Accessed Address: {:?}", addr);
     println!("Error Code: {:?}", error_code);
     println!("{:#?}", stack_frame);
     hlt_loop();
@@ -100,6 +110,12 @@ extern "x86-interrupt" fn breakpoint_handler(
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame, _error_code: u64) -> !
 {
+    use x86_64::registers::control::Cr2;
+
+    // a fault on a guard page can't be handled on the overflowed stack
+    if let Some(stack) = crate::memory::stack::guard_page_owner(Cr2::read()) {
+        panic!("EXCEPTION: DOUBLE FAULT\nstack overflow on stack {}\n{:#?}", stack, stack_frame);
+    }
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
@@ -16,10 +16,11 @@ pub mod task;
 
 extern crate alloc;
 
+use bootloader::BootInfo;
 use core::panic::PanicInfo;
 
 #[cfg(test)]
-use bootloader::{entry_point, BootInfo};
+use bootloader::entry_point;
 
 /// Entry point for `cargo test`
 #[cfg(test)]
@@ -96,4 +97,22 @@ pub fn init() {
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
     x86_64::instructions::interrupts::enable();
-}
\ No newline at end of file
+}
+
+/// Boots the kernel for an integration test or benchmark: runs `init`, sets
+/// up the frame allocator and the heap, and installs them with
+/// `memory::install`.
+pub fn test_boot(boot_info: &'static BootInfo) {
+    use memory::BitmapFrameAllocator;
+    use x86_64::VirtAddr;
+
+    init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+}
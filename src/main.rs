@@ -19,7 +19,7 @@ entry_point!(kernel_main);
 
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
     use os::allocator; // new import
-    use os::memory::{self, BootInfoFrameAllocator};
+    use os::memory::{self, BitmapFrameAllocator};
 
     
     println!("Hello World{}", "!");
@@ -28,11 +28,20 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
     let mut frame_allocator = unsafe {
-            BootInfoFrameAllocator::init(&boot_info.memory_map)
+            BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
         };
 
     allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("heap initialization failed");
+    unsafe { memory::wx::enforce(&mut mapper, &mut frame_allocator) }
+        .expect("failed to remap kernel with W^X permissions");
+    if let Err(range) = memory::wx::check(&mut mapper) {
+        panic!("writable and executable mapping: {:?}", range);
+    }
+    memory::stack::register_boot_stack(&mapper);
+    memory::install(mapper, frame_allocator);
+    println!("{}", memory::stats::MemoryMapSummary::new(&boot_info.memory_map));
+    println!("{}", memory::stats::meminfo().unwrap());
     
     // as before
     #[cfg(test)]
@@ -1,3 +1,20 @@
+pub mod address_space;
+pub mod bitmap;
+pub mod buddy;
+pub mod cow;
+pub mod demand;
+pub mod mapping;
+pub mod mmio;
+pub mod stack;
+pub mod stats;
+pub mod vma;
+pub mod walker;
+pub mod wx;
+
+pub use address_space::AddressSpace;
+pub use bitmap::BitmapFrameAllocator;
+pub use buddy::BuddyAllocator;
+
 use x86_64::{
     structures::paging::PageTable,
     VirtAddr,
@@ -115,4 +132,24 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr)
     unsafe { &mut *page_table_ptr }
 }
 
+/// The kernel's page table together with the frame allocator, for code that
+/// needs to map memory after boot, e.g. to grow the heap.
+pub struct KernelMemory {
+    pub mapper: OffsetPageTable<'static>,
+    pub frame_allocator: BitmapFrameAllocator,
+}
+
+/// The kernel memory, available after `install` was called.
+///
+/// Code that holds this lock must not allocate on the heap, because the heap
+/// may need the lock to grow.
+pub static KERNEL_MEMORY: spin::Mutex<Option<KernelMemory>> = spin::Mutex::new(None);
 
+/// Makes the given page table and frame allocator available through
+/// `KERNEL_MEMORY` and allocates the interrupt stacks.
+///
+/// The heap must be initialized before.
+pub fn install(mapper: OffsetPageTable<'static>, frame_allocator: BitmapFrameAllocator) {
+    *KERNEL_MEMORY.lock() = Some(KernelMemory { mapper, frame_allocator });
+    crate::gdt::init_ist_stacks();
+}
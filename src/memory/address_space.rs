@@ -0,0 +1,206 @@
+use super::mapping::MapError;
+use core::ops::Range;
+use x86_64::{
+    VirtAddr,
+    registers::control::Cr3,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable,
+        PageTableFlags, PhysFrame, Size4KiB,
+        mapper::{MapToError, MapperFlush},
+    },
+};
+
+/// The level 4 entries that every address space shares with the kernel.
+///
+/// The kernel is linked into the lower half and the bootloader puts the boot
+/// stack, boot info and physical memory mapping into the lowest free level 4
+/// entries, so unlike in most kernels the lower half is the kernel half here.
+pub const KERNEL_ENTRIES: Range<usize> = 0..256;
+
+/// Start of the part of an address space that is private to it.
+pub const PRIVATE_START: u64 = 0xffff_8000_0000_0000;
+
+/// A set of page tables whose upper half is private and whose lower half is
+/// shared with the kernel.
+///
+/// The kernel half is shared at the level 4 entry granularity: changes below
+/// existing kernel entries are visible in every address space, but level 4
+/// entries that the kernel creates later are not copied into address spaces
+/// that already exist.
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+    mapper: OffsetPageTable<'static>,
+}
+
+impl AddressSpace {
+    /// Creates a new address space with an empty private half.
+    ///
+    /// The kernel half is copied from the level 4 table of `kernel`. Returns
+    /// `None` if no frame for the level 4 table could be allocated.
+    pub fn new(
+        kernel: &mut OffsetPageTable,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    ) -> Option<Self> {
+        let phys_offset = kernel.phys_offset();
+        let level_4_frame = frame_allocator.allocate_frame()?;
+        let table_ptr: *mut PageTable =
+            (phys_offset + level_4_frame.start_address().as_u64()).as_mut_ptr();
+        let table = unsafe {
+            table_ptr.write(PageTable::new());
+            &mut *table_ptr
+        };
+        let kernel_table = kernel.level_4_table();
+        for index in KERNEL_ENTRIES {
+            table[index] = kernel_table[index].clone();
+        }
+
+        let mapper = unsafe { OffsetPageTable::new(table, phys_offset) };
+        Some(AddressSpace { level_4_frame, mapper })
+    }
+
+    /// Returns the frame of the level 4 table, i.e. the value for `Cr3`.
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// Returns whether this address space is the currently active one.
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    /// Returns the mapper of this address space, e.g. for use with the range
+    /// functions of the `mapping` module.
+    ///
+    /// Unlike `map`, `unmap` and `protect`, the mapper does not prevent
+    /// changes to the shared kernel half.
+    pub fn mapper(&mut self) -> &mut OffsetPageTable<'static> {
+        &mut self.mapper
+    }
+
+    /// Maps the given page of the private half to `frame`.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// frame is not in use by anything else, e.g. as a frame of the heap.
+    pub unsafe fn map(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    ) -> Result<(), MapError> {
+        Self::check_private(page)?;
+        // the parent entries need the user bit for user pages to be accessible
+        let parent_flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | (flags & PageTableFlags::USER_ACCESSIBLE);
+        let result = unsafe {
+            self.mapper
+                .map_to_with_table_flags(page, frame, flags, parent_flags, frame_allocator)
+        };
+        match result {
+            Ok(flush) => {
+                self.flush(flush);
+                Ok(())
+            }
+            Err(MapToError::FrameAllocationFailed) => Err(MapError::FrameAllocationFailed),
+            Err(_) => Err(MapError::PageAlreadyMapped(page.start_address())),
+        }
+    }
+
+    /// Unmaps the given page of the private half and returns the frame it was
+    /// mapped to.
+    pub fn unmap(&mut self, page: Page) -> Result<PhysFrame, MapError> {
+        Self::check_private(page)?;
+        let (frame, flush) = self
+            .mapper
+            .unmap(page)
+            .map_err(|_| MapError::NotMapped(page.start_address()))?;
+        self.flush(flush);
+        Ok(frame)
+    }
+
+    /// Changes the flags of the given page of the private half.
+    ///
+    /// This function is unsafe because changing the flags of pages that are in
+    /// use can break memory safety.
+    pub unsafe fn protect(&mut self, page: Page, flags: PageTableFlags) -> Result<(), MapError> {
+        Self::check_private(page)?;
+        let flush = unsafe { self.mapper.update_flags(page, flags) }
+            .map_err(|_| MapError::NotMapped(page.start_address()))?;
+        self.flush(flush);
+        Ok(())
+    }
+
+    fn check_private(page: Page) -> Result<(), MapError> {
+        if page.start_address().as_u64() < PRIVATE_START {
+            return Err(MapError::KernelHalf(page.start_address()));
+        }
+        Ok(())
+    }
+
+    /// Flushes the changed TLB entry if this address space is active.
+    fn flush(&self, flush: MapperFlush<Size4KiB>) {
+        if self.is_active() {
+            flush.flush();
+        } else {
+            // loading `Cr3` on the next switch flushes all non-global entries
+            flush.ignore();
+        }
+    }
+
+    /// Loads this address space into `Cr3` and returns the level 4 frame that
+    /// was active before.
+    ///
+    /// This function is unsafe because the caller must guarantee that nothing
+    /// on the current stack or in use by the caller lives in the private half
+    /// of the previously active address space.
+    pub unsafe fn activate(&self) -> PhysFrame {
+        let (previous, flags) = Cr3::read();
+        unsafe { Cr3::write(self.level_4_frame, flags) };
+        previous
+    }
+
+    /// Frees all page tables of the private half, the frames mapped by its
+    /// 4 KiB pages and the level 4 table.
+    ///
+    /// Frames mapped by huge pages are not freed. Frames that were not
+    /// obtained from `frame_allocator`, e.g. of memory-mapped devices, must
+    /// be unmapped before.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// frames mapped in the private half are not in use anywhere else.
+    pub unsafe fn destroy(mut self, frame_allocator: &mut impl FrameDeallocator<Size4KiB>) {
+        assert!(!self.is_active(), "cannot destroy the active address space");
+        let phys_offset = self.mapper.phys_offset();
+        let table = self.mapper.level_4_table();
+        for entry in table.iter().skip(KERNEL_ENTRIES.end) {
+            if !entry.is_unused() {
+                unsafe { free_table(entry.frame().unwrap(), 3, phys_offset, frame_allocator) };
+            }
+        }
+        unsafe { frame_allocator.deallocate_frame(self.level_4_frame) };
+    }
+}
+
+/// Frees the page table in `frame` together with all tables and 4 KiB frames
+/// below it. `level` is 3 for a level 3 table and 1 for a level 1 table.
+unsafe fn free_table(
+    frame: PhysFrame,
+    level: usize,
+    phys_offset: VirtAddr,
+    frame_allocator: &mut impl FrameDeallocator<Size4KiB>,
+) {
+    let table: &PageTable = unsafe { &*(phys_offset + frame.start_address().as_u64()).as_ptr() };
+    for entry in table.iter() {
+        if entry.is_unused() || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            continue;
+        }
+        let child = PhysFrame::containing_address(entry.addr());
+        if level == 1 {
+            unsafe { frame_allocator.deallocate_frame(child) };
+        } else {
+            unsafe { free_table(child, level - 1, phys_offset, frame_allocator) };
+        }
+    }
+    unsafe { frame_allocator.deallocate_frame(frame) };
+}
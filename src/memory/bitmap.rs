@@ -0,0 +1,260 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::slice;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB, frame::PhysFrameRange,
+    },
+};
+
+const FRAME_SIZE: u64 = 4096;
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// A frame allocator that tracks every physical frame in a bitmap.
+///
+/// A set bit marks a used frame. A second, much smaller summary bitmap has one
+/// bit per bitmap word that is set while that word still contains a free frame.
+/// One summary word covers 4096 frames (16 MiB), so finding a free frame only
+/// looks at a handful of words, and freeing a frame is constant time.
+///
+/// Frames can be shared between several mappings with `share`, which is
+/// tracked in a per-frame reference count. The count only records references
+/// beyond the first one, so plain allocations never touch it.
+///
+/// The bitmaps and reference counts live in the first usable region that is
+/// large enough to hold them and are accessed through the physical memory
+/// mapping.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    summary: &'static mut [u64],
+    /// A set bit marks a frame that this allocator hands out, which excludes
+    /// frames outside of usable regions and the frames of the metadata.
+    managed: &'static mut [u64],
+    /// Number of additional references to each frame.
+    shared: &'static mut [u16],
+    /// Summary word to start the next search at.
+    next: usize,
+    usable_frames: usize,
+    free_frames: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// Create a BitmapFrameAllocator from the passed memory map.
+    ///
+    /// This function is unsafe because the caller must guarantee that the passed
+    /// memory map is valid and that all frames marked as `USABLE` in it are
+    /// really unused. The complete physical memory must be mapped at
+    /// `physical_memory_offset`.
+    pub unsafe fn init(memory_map: &MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let usable_regions = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+        };
+
+        // only track frames up to the end of the highest usable region
+        let max_addr = usable_regions()
+            .map(|r| r.range.end_addr())
+            .max()
+            .unwrap_or(0);
+        let frame_count = (max_addr / FRAME_SIZE) as usize;
+        let bitmap_words = frame_count.div_ceil(BITS_PER_WORD);
+        let summary_words = bitmap_words.div_ceil(BITS_PER_WORD);
+        let metadata_bytes = ((2 * bitmap_words + summary_words) * 8 + frame_count * 2) as u64;
+        let metadata_size = metadata_bytes.div_ceil(FRAME_SIZE) * FRAME_SIZE;
+
+        let metadata_region = usable_regions()
+            .find(|r| r.range.end_addr() - r.range.start_addr() >= metadata_size)
+            .expect("no usable region large enough for the frame bitmap");
+        let metadata_start = metadata_region.range.start_addr();
+
+        let metadata_ptr: *mut u64 =
+            (physical_memory_offset + metadata_start).as_mut_ptr();
+        let (bitmap, summary, managed, shared) = unsafe {
+            let summary_ptr = metadata_ptr.add(bitmap_words);
+            let managed_ptr = summary_ptr.add(summary_words);
+            let shared_ptr = managed_ptr.add(bitmap_words).cast::<u16>();
+            (
+                slice::from_raw_parts_mut(metadata_ptr, bitmap_words),
+                slice::from_raw_parts_mut(summary_ptr, summary_words),
+                slice::from_raw_parts_mut(managed_ptr, bitmap_words),
+                slice::from_raw_parts_mut(shared_ptr, frame_count),
+            )
+        };
+
+        // start with every frame marked as used and free the usable ones
+        bitmap.fill(!0);
+        summary.fill(0);
+        managed.fill(0);
+        shared.fill(0);
+        let mut allocator = BitmapFrameAllocator {
+            bitmap,
+            summary,
+            managed,
+            shared,
+            next: 0,
+            usable_frames: 0,
+            free_frames: 0,
+        };
+        for region in usable_regions() {
+            let addr_range = region.range.start_addr()..region.range.end_addr();
+            for addr in addr_range.step_by(FRAME_SIZE as usize) {
+                allocator.usable_frames += 1;
+                if !(metadata_start..metadata_start + metadata_size).contains(&addr) {
+                    let index = Self::index_of(PhysAddr::new(addr));
+                    allocator.managed[index / BITS_PER_WORD] |= 1 << (index % BITS_PER_WORD);
+                    allocator.mark_free(index);
+                }
+            }
+        }
+        allocator
+    }
+
+    /// Returns the number of usable frames managed by this allocator.
+    pub fn usable_frames(&self) -> usize {
+        self.usable_frames
+    }
+
+    /// Returns the number of frames that are currently free.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Returns the number of usable frames that are currently allocated,
+    /// including the frames holding the bitmap itself.
+    pub fn used_frames(&self) -> usize {
+        self.usable_frames - self.free_frames
+    }
+
+    /// Returns whether the given frame is currently allocated.
+    ///
+    /// Frames outside of the tracked range are reported as used.
+    pub fn is_used(&self, frame: PhysFrame) -> bool {
+        let index = Self::index_of(frame.start_address());
+        match self.bitmap.get(index / BITS_PER_WORD) {
+            Some(word) => word & (1 << (index % BITS_PER_WORD)) != 0,
+            None => true,
+        }
+    }
+
+    /// Returns whether the given frame is handed out by this allocator, i.e.
+    /// lies in a usable region and does not hold the allocator's metadata.
+    pub fn is_managed(&self, frame: PhysFrame) -> bool {
+        let index = Self::index_of(frame.start_address());
+        match self.managed.get(index / BITS_PER_WORD) {
+            Some(word) => word & (1 << (index % BITS_PER_WORD)) != 0,
+            None => false,
+        }
+    }
+
+    /// Returns the number of references to the given frame, which is `0` for
+    /// free frames and frames that are not managed by this allocator.
+    pub fn ref_count(&self, frame: PhysFrame) -> usize {
+        let index = Self::index_of(frame.start_address());
+        if self.is_managed(frame) && self.is_used(frame) {
+            self.shared[index] as usize + 1
+        } else {
+            0
+        }
+    }
+
+    /// Adds a reference to an allocated frame.
+    ///
+    /// Every reference has to be dropped with `deallocate_frame` before the
+    /// frame is freed.
+    pub fn share(&mut self, frame: PhysFrame) {
+        let index = Self::index_of(frame.start_address());
+        assert!(self.is_managed(frame), "frame {:?} is not managed by this allocator", frame);
+        assert!(self.is_used(frame), "frame {:?} is not allocated", frame);
+        self.shared[index] = self.shared[index]
+            .checked_add(1)
+            .expect("too many references to frame");
+    }
+
+    /// Removes `count` contiguous free frames that end below `limit` from this
+    /// allocator for good and returns them.
+    ///
+    /// The frames are no longer managed afterwards, so they can be handed to
+    /// another allocator like the `BuddyAllocator` without the two ever
+    /// returning the same frame. The lowest fitting range is used.
+    pub fn carve_out(&mut self, count: usize, limit: PhysAddr) -> Option<PhysFrameRange> {
+        assert!(count > 0, "cannot carve out an empty range");
+        let limit = Self::index_of(limit).min(self.bitmap.len() * BITS_PER_WORD);
+        let mut run_start = 0;
+        for index in 0..limit {
+            let frame = PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE));
+            if !self.is_managed(frame) || self.is_used(frame) {
+                run_start = index + 1;
+                continue;
+            }
+            if index + 1 - run_start < count {
+                continue;
+            }
+            for index in run_start..=index {
+                let word = index / BITS_PER_WORD;
+                let bit = 1 << (index % BITS_PER_WORD);
+                self.bitmap[word] |= bit;
+                self.managed[word] &= !bit;
+                if self.bitmap[word] == !0 {
+                    self.summary[word / BITS_PER_WORD] &= !(1 << (word % BITS_PER_WORD));
+                }
+            }
+            self.free_frames -= count;
+            self.usable_frames -= count;
+            let start = PhysAddr::new(run_start as u64 * FRAME_SIZE);
+            let start = PhysFrame::containing_address(start);
+            return Some(PhysFrame::range(start, start + count as u64));
+        }
+        None
+    }
+
+    fn index_of(addr: PhysAddr) -> usize {
+        (addr.as_u64() / FRAME_SIZE) as usize
+    }
+
+    /// Clears the bit of the given frame and updates the summary.
+    fn mark_free(&mut self, index: usize) {
+        let word = index / BITS_PER_WORD;
+        self.bitmap[word] &= !(1 << (index % BITS_PER_WORD));
+        self.summary[word / BITS_PER_WORD] |= 1 << (word % BITS_PER_WORD);
+        self.free_frames += 1;
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let summary_len = self.summary.len();
+        // look for a summary word with a set bit, starting at the hint
+        let summary_index = (0..summary_len)
+            .map(|i| (self.next + i) % summary_len)
+            .find(|&i| self.summary[i] != 0)?;
+        self.next = summary_index;
+
+        let word = summary_index * BITS_PER_WORD
+            + self.summary[summary_index].trailing_zeros() as usize;
+        let bit = (!self.bitmap[word]).trailing_zeros() as usize;
+        self.bitmap[word] |= 1 << bit;
+        if self.bitmap[word] == !0 {
+            // no free frame left in this word
+            self.summary[summary_index] &= !(1 << (word % BITS_PER_WORD));
+        }
+        self.free_frames -= 1;
+
+        let addr = (word * BITS_PER_WORD + bit) as u64 * FRAME_SIZE;
+        Some(PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+}
+
+/// Drops a reference to the frame and frees it once no references are left.
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let index = Self::index_of(frame.start_address());
+        assert!(self.is_managed(frame), "frame {:?} is not managed by this allocator", frame);
+        assert!(self.is_used(frame), "double free of frame {:?}", frame);
+        if self.shared[index] > 0 {
+            self.shared[index] -= 1;
+            return;
+        }
+        self.mark_free(index);
+    }
+}
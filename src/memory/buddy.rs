@@ -0,0 +1,305 @@
+use core::slice;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size1GiB, Size2MiB, Size4KiB,
+        frame::PhysFrameRange,
+    },
+};
+
+const FRAME_SIZE: u64 = 4096;
+
+/// The largest supported order. Blocks of order `n` span `2^n` frames, so
+/// the orders range from 4 KiB (order 0) to 1 GiB (order 18).
+pub const MAX_ORDER: usize = 18;
+
+/// Marks the end of a free list.
+const NONE: u64 = u64::MAX;
+
+/// Set in `block_orders` for frames that start an allocated block.
+const ALLOCATED: u8 = 0x80;
+
+/// Upper bounds of the zones, see [`Constraint`].
+const ZONE_LIMITS: [u64; 3] = [16 << 20, 4 << 30, u64::MAX];
+
+/// Restricts the physical addresses an allocation may come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Constraint {
+    /// The block may lie anywhere in physical memory.
+    Any,
+    /// The block must end below 4 GiB, e.g. for devices with 32-bit DMA.
+    Below4GiB,
+    /// The block must end below 16 MiB, e.g. for legacy ISA DMA.
+    Below16MiB,
+}
+
+impl Constraint {
+    /// Returns the number of zones (starting from the lowest) that satisfy
+    /// this constraint.
+    fn zone_count(self) -> usize {
+        match self {
+            Constraint::Any => 3,
+            Constraint::Below4GiB => 2,
+            Constraint::Below16MiB => 1,
+        }
+    }
+}
+
+/// Returns the order of the block that is needed for the given page size.
+pub const fn order_of<S: PageSize>() -> usize {
+    (S::SIZE / FRAME_SIZE).trailing_zeros() as usize
+}
+
+/// Header written to the start of every free block.
+struct FreeBlock {
+    next: u64,
+    prev: u64,
+}
+
+/// A buddy-system allocator for physically contiguous frames.
+///
+/// Physical memory is split into three zones (below 16 MiB, below 4 GiB and
+/// the rest) that each keep one doubly linked free list per order. The list
+/// nodes are stored in the free blocks themselves. A byte per frame records
+/// the order of the free or allocated block starting at that frame, which lets
+/// `deallocate` find and merge a free buddy and detect double frees in
+/// constant time. Blocks are never merged across zone boundaries.
+///
+/// The allocator manages a single range of frames. `memory::install` does not
+/// set one up, so the caller has to carve the range out of the
+/// `BitmapFrameAllocator` with `carve_out`, so that both never hand out the
+/// same frame.
+pub struct BuddyAllocator {
+    physical_memory_offset: VirtAddr,
+    /// Index of the first managed frame.
+    base: usize,
+    /// `order + 1` for frames that start a free block, `ALLOCATED | order`
+    /// for frames that start an allocated block, `0` otherwise.
+    block_orders: &'static mut [u8],
+    free_lists: [[u64; MAX_ORDER + 1]; 3],
+    usable_frames: usize,
+    free_frames: usize,
+}
+
+impl BuddyAllocator {
+    /// Create a BuddyAllocator that manages the given frames, e.g. a range
+    /// returned by `BitmapFrameAllocator::carve_out`. The first frames of the
+    /// range hold the allocator's metadata.
+    ///
+    /// This function is unsafe because the caller must guarantee that the frames
+    /// are unused and not managed by any other allocator. The complete physical
+    /// memory must be mapped at `physical_memory_offset`.
+    pub unsafe fn init(frames: PhysFrameRange, physical_memory_offset: VirtAddr) -> Self {
+        let start = frames.start.start_address().as_u64();
+        let end = frames.end.start_address().as_u64();
+        let frame_count = ((end - start) / FRAME_SIZE) as usize;
+        let metadata_size = (frame_count as u64).div_ceil(FRAME_SIZE) * FRAME_SIZE;
+        assert!(metadata_size < end - start, "frame range too small for the buddy allocator");
+
+        let metadata_ptr: *mut u8 = (physical_memory_offset + start).as_mut_ptr();
+        let block_orders = unsafe { slice::from_raw_parts_mut(metadata_ptr, frame_count) };
+        block_orders.fill(0);
+
+        let mut allocator = BuddyAllocator {
+            physical_memory_offset,
+            base: (start / FRAME_SIZE) as usize,
+            block_orders,
+            free_lists: [[NONE; MAX_ORDER + 1]; 3],
+            usable_frames: frame_count,
+            free_frames: 0,
+        };
+        unsafe { allocator.add_range(start + metadata_size, end) };
+        allocator
+    }
+
+    /// Frees the given physical range as a sequence of maximally sized
+    /// blocks. Partial frames at either end are left out.
+    unsafe fn add_range(&mut self, start: u64, end: u64) {
+        let mut start = start.next_multiple_of(FRAME_SIZE);
+        let end = end / FRAME_SIZE * FRAME_SIZE;
+        while start < end {
+            let zone_end = ZONE_LIMITS[Self::zone_of(start)].min(end);
+            let mut order = MAX_ORDER;
+            while !start.is_multiple_of(Self::block_size(order))
+                || start + Self::block_size(order) > zone_end
+            {
+                order -= 1;
+            }
+            unsafe { self.free_block(start, order) };
+            start += Self::block_size(order);
+        }
+    }
+
+    /// Allocates `2^order` physically contiguous frames that satisfy the
+    /// given constraint.
+    ///
+    /// The returned block is aligned to its size. Higher zones are tried
+    /// first so that low memory stays available for constrained allocations.
+    pub fn allocate(&mut self, order: usize, constraint: Constraint) -> Option<PhysFrame> {
+        assert!(order <= MAX_ORDER, "order {} is too large", order);
+        for zone in (0..constraint.zone_count()).rev() {
+            let free_lists = &self.free_lists[zone];
+            let Some(found) = (order..=MAX_ORDER).find(|&o| free_lists[o] != NONE) else {
+                continue;
+            };
+            let addr = self.free_lists[zone][found];
+            self.remove(zone, found, addr);
+            // split the block, returning the upper halves to the free lists
+            for split in (order..found).rev() {
+                self.push(zone, split, addr + Self::block_size(split));
+            }
+            let index = self.index_of(addr).unwrap();
+            self.block_orders[index] = ALLOCATED | order as u8;
+            self.free_frames -= 1 << order;
+            return Some(PhysFrame::containing_address(PhysAddr::new(addr)));
+        }
+        None
+    }
+
+    /// Returns a block of `2^order` frames that was obtained from `allocate`
+    /// and merges it with its free buddies.
+    ///
+    /// This function is unsafe because the caller must guarantee that the block
+    /// was allocated with the same order and is no longer in use.
+    pub unsafe fn deallocate(&mut self, frame: PhysFrame, order: usize) {
+        let addr = frame.start_address().as_u64();
+        assert!(addr.is_multiple_of(Self::block_size(order)), "block is not aligned to its order");
+        let end = addr + Self::block_size(order);
+        assert!(
+            self.index_of(addr).is_some() && self.index_of(end - FRAME_SIZE).is_some(),
+            "block {:#x} is not managed by this allocator",
+            addr
+        );
+        assert_eq!(
+            self.order_entry(addr),
+            (ALLOCATED | order as u8) as usize,
+            "block {:#x} is not allocated with order {}, or was freed twice",
+            addr,
+            order
+        );
+        let index = self.index_of(addr).unwrap();
+        self.block_orders[index] = 0;
+        unsafe { self.free_block(addr, order) };
+    }
+
+    unsafe fn free_block(&mut self, mut addr: u64, mut order: usize) {
+        let zone = Self::zone_of(addr);
+        self.free_frames += 1 << order;
+        while order < MAX_ORDER {
+            let buddy = addr ^ Self::block_size(order);
+            let buddy_is_free = Self::zone_of(buddy) == zone && self.order_entry(buddy) == order + 1;
+            if !buddy_is_free {
+                break;
+            }
+            self.remove(zone, order, buddy);
+            addr = addr.min(buddy);
+            order += 1;
+        }
+        self.push(zone, order, addr);
+    }
+
+    /// Returns the number of usable frames managed by this allocator.
+    pub fn usable_frames(&self) -> usize {
+        self.usable_frames
+    }
+
+    /// Returns the number of frames that are currently free.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Returns the number of free blocks of the given order across all zones.
+    pub fn free_blocks(&self, order: usize) -> usize {
+        let mut count = 0;
+        for zone in 0..ZONE_LIMITS.len() {
+            let mut addr = self.free_lists[zone][order];
+            while addr != NONE {
+                count += 1;
+                addr = unsafe { (*self.node(addr)).next };
+            }
+        }
+        count
+    }
+
+    fn block_size(order: usize) -> u64 {
+        FRAME_SIZE << order
+    }
+
+    /// Returns the index of the frame at `addr` in `block_orders`, if the
+    /// frame is managed by this allocator.
+    fn index_of(&self, addr: u64) -> Option<usize> {
+        let index = ((addr / FRAME_SIZE) as usize).checked_sub(self.base)?;
+        (index < self.block_orders.len()).then_some(index)
+    }
+
+    /// Returns the `block_orders` entry of the frame at `addr`, or `0` if
+    /// the frame is not managed by this allocator.
+    fn order_entry(&self, addr: u64) -> usize {
+        self.index_of(addr).map_or(0, |index| self.block_orders[index] as usize)
+    }
+
+    fn zone_of(addr: u64) -> usize {
+        ZONE_LIMITS.iter().position(|&limit| addr < limit).unwrap()
+    }
+
+    /// Returns a pointer to the header of the free block at `addr`.
+    fn node(&self, addr: u64) -> *mut FreeBlock {
+        (self.physical_memory_offset + addr).as_mut_ptr()
+    }
+
+    /// Pushes the block at `addr` to the front of the given free list.
+    fn push(&mut self, zone: usize, order: usize, addr: u64) {
+        let head = self.free_lists[zone][order];
+        unsafe {
+            self.node(addr).write(FreeBlock { next: head, prev: NONE });
+            if head != NONE {
+                (*self.node(head)).prev = addr;
+            }
+        }
+        self.free_lists[zone][order] = addr;
+        let index = self.index_of(addr).unwrap();
+        self.block_orders[index] = order as u8 + 1;
+    }
+
+    /// Unlinks the free block at `addr` from the given free list.
+    fn remove(&mut self, zone: usize, order: usize, addr: u64) {
+        let FreeBlock { next, prev } = unsafe { self.node(addr).read() };
+        if prev == NONE {
+            self.free_lists[zone][order] = next;
+        } else {
+            unsafe { (*self.node(prev)).next = next };
+        }
+        if next != NONE {
+            unsafe { (*self.node(next)).prev = prev };
+        }
+        let index = self.index_of(addr).unwrap();
+        self.block_orders[index] = 0;
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        self.allocate(order_of::<Size4KiB>(), Constraint::Any)
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BuddyAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let frame = self.allocate(order_of::<Size2MiB>(), Constraint::Any)?;
+        PhysFrame::from_start_address(frame.start_address()).ok()
+    }
+}
+
+unsafe impl FrameAllocator<Size1GiB> for BuddyAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size1GiB>> {
+        let frame = self.allocate(order_of::<Size1GiB>(), Constraint::Any)?;
+        PhysFrame::from_start_address(frame.start_address()).ok()
+    }
+}
+
+impl<S: PageSize> FrameDeallocator<S> for BuddyAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>) {
+        let frame = PhysFrame::containing_address(frame.start_address());
+        unsafe { self.deallocate(frame, order_of::<S>()) }
+    }
+}
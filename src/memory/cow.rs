@@ -0,0 +1,140 @@
+use super::{KERNEL_MEMORY, KernelMemory, BitmapFrameAllocator, mapping::MapError};
+use x86_64::{
+    VirtAddr,
+    structures::{
+        idt::PageFaultErrorCode,
+        paging::{
+            FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize,
+            PageTableFlags, PhysFrame, Size4KiB, Translate,
+            mapper::{MapToError, MappedFrame, TranslateResult},
+        },
+    },
+};
+
+/// Marks a page that was writable before it got shared. The first write to
+/// it copies the frame, see `handle_page_fault`.
+///
+/// Bit 9 is one of the page table entry bits that are ignored by the CPU.
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Maps the pages of `src..src + len` a second time at `dst`, sharing their
+/// frames between both mappings.
+///
+/// Writable pages become read-only copy-on-write pages in both mappings, so
+/// that the first write to either of them gives it a private copy. Every
+/// shared frame gets an additional reference, which is dropped again when
+/// the frame is deallocated by the owner of one of the mappings, so all
+/// source pages must be backed by frames of the frame allocator. If an error
+/// occurs, the pages before the failing one stay shared.
+///
+/// This function is unsafe because the caller must guarantee that nothing
+/// relies on the source pages staying writable without a page fault, e.g.
+/// code that runs while the kernel memory is locked.
+pub unsafe fn share_range(
+    mapper: &mut OffsetPageTable,
+    src: VirtAddr,
+    dst: VirtAddr,
+    len: u64,
+    frame_allocator: &mut BitmapFrameAllocator,
+) -> Result<(), MapError> {
+    if !src.is_aligned(Size4KiB::SIZE) || !dst.is_aligned(Size4KiB::SIZE)
+        || !len.is_multiple_of(Size4KiB::SIZE)
+    {
+        return Err(MapError::Unaligned);
+    }
+
+    for offset in (0..len).step_by(Size4KiB::SIZE as usize) {
+        let (frame, flags) = mapped_page(mapper, src + offset)?;
+        let mut flags = flags - (PageTableFlags::ACCESSED | PageTableFlags::DIRTY);
+        if flags.contains(PageTableFlags::WRITABLE) {
+            flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+        }
+
+        let dst_page = Page::<Size4KiB>::containing_address(dst + offset);
+        match unsafe { mapper.map_to(dst_page, frame, flags, frame_allocator) } {
+            Ok(flush) => flush.flush(),
+            Err(MapToError::FrameAllocationFailed) => return Err(MapError::FrameAllocationFailed),
+            Err(_) => return Err(MapError::PageAlreadyMapped(dst + offset)),
+        }
+        let src_page = Page::<Size4KiB>::containing_address(src + offset);
+        unsafe { mapper.update_flags(src_page, flags) }
+            .map_err(|_| MapError::NotMapped(src + offset))?
+            .flush();
+        frame_allocator.share(frame);
+    }
+    Ok(())
+}
+
+/// Returns the frame and flags of the 4 KiB page mapped at `addr`.
+fn mapped_page(
+    mapper: &OffsetPageTable,
+    addr: VirtAddr,
+) -> Result<(PhysFrame, PageTableFlags), MapError> {
+    match mapper.translate(addr) {
+        TranslateResult::Mapped { frame: MappedFrame::Size4KiB(frame), flags, .. } => {
+            Ok((frame, flags))
+        }
+        TranslateResult::Mapped { .. } => Err(MapError::HugePage(addr)),
+        _ => Err(MapError::NotMapped(addr)),
+    }
+}
+
+/// Resolves a write fault on a copy-on-write page.
+///
+/// If other mappings still reference the frame, its content is copied into
+/// a new frame that is mapped writable in its place. The last remaining
+/// mapping is simply made writable again. Returns `false` if the fault is not
+/// caused by a write to a copy-on-write page or if the kernel memory is
+/// locked.
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    let write_violation =
+        PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    if !error_code.contains(write_violation) {
+        return false;
+    }
+    let Some(mut memory) = KERNEL_MEMORY.try_lock() else {
+        return false;
+    };
+    let Some(KernelMemory { mapper, frame_allocator }) = memory.as_mut() else {
+        return false;
+    };
+    let Ok((frame, flags)) = mapped_page(mapper, addr) else {
+        return false;
+    };
+    if !flags.contains(COPY_ON_WRITE) {
+        return false;
+    }
+
+    let page = Page::<Size4KiB>::containing_address(addr);
+    let flags = (flags - COPY_ON_WRITE) | PageTableFlags::WRITABLE;
+    if frame_allocator.ref_count(frame) == 1 {
+        return match unsafe { mapper.update_flags(page, flags) } {
+            Ok(flush) => {
+                flush.flush();
+                true
+            }
+            Err(_) => false,
+        };
+    }
+
+    let Some(copy) = frame_allocator.allocate_frame() else {
+        return false;
+    };
+    let phys_offset = mapper.phys_offset();
+    let src: *const u8 = (phys_offset + frame.start_address().as_u64()).as_ptr();
+    let dst: *mut u8 = (phys_offset + copy.start_address().as_u64()).as_mut_ptr();
+    unsafe { dst.copy_from_nonoverlapping(src, Size4KiB::SIZE as usize) };
+
+    let Ok((_, flush)) = mapper.unmap(page) else {
+        unsafe { frame_allocator.deallocate_frame(copy) };
+        return false;
+    };
+    flush.flush();
+    match unsafe { mapper.map_to(page, copy, flags, frame_allocator) } {
+        Ok(flush) => flush.flush(),
+        Err(err) => panic!("failed to map copy-on-write page: {:?}", err),
+    }
+    // drop the reference of this mapping to the shared frame
+    unsafe { frame_allocator.deallocate_frame(frame) };
+    true
+}
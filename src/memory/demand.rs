@@ -0,0 +1,94 @@
+use super::{
+    KERNEL_MEMORY, KernelMemory,
+    vma::{Backing, KERNEL_VMAS, Owner, VmaError},
+};
+use x86_64::{
+    VirtAddr,
+    structures::{
+        idt::PageFaultErrorCode,
+        paging::{
+            FrameAllocator, FrameDeallocator, Mapper, Page, PageSize, PageTableFlags, Size4KiB,
+        },
+    },
+};
+
+/// Reserves `size` bytes of kernel virtual memory that are backed by zeroed
+/// frames on first access.
+pub fn reserve(size: u64, owner: Owner, flags: PageTableFlags) -> Result<VirtAddr, VmaError> {
+    let size = size.next_multiple_of(Size4KiB::SIZE);
+    KERNEL_VMAS
+        .lock()
+        .reserve(size, Size4KiB::SIZE, owner, flags, Backing::Demand)
+}
+
+/// Releases a demand-paged area and frees all frames that were mapped in it.
+///
+/// This function is unsafe because the caller must guarantee that the area is
+/// no longer accessed.
+pub unsafe fn release(start: VirtAddr) {
+    let vma = KERNEL_VMAS.lock().release(start).expect("no area at the given address");
+    assert_eq!(vma.backing, Backing::Demand, "area is not demand-paged");
+
+    let mut memory = KERNEL_MEMORY.lock();
+    let KernelMemory { mapper, frame_allocator } =
+        memory.as_mut().expect("kernel memory not installed");
+    let pages = Page::<Size4KiB>::range(
+        Page::containing_address(vma.start),
+        Page::containing_address(vma.end()),
+    );
+    for page in pages {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            unsafe { frame_allocator.deallocate_frame(frame) };
+        }
+    }
+}
+
+/// Maps a zeroed frame for a not-present fault inside a demand-paged area.
+///
+/// Returns `false` if the fault is not caused by a missing demand page or if
+/// the access is not permitted by the flags of the area. The kernel memory
+/// and the area list are only try-locked, so a fault while either is held is
+/// reported as unhandled instead of deadlocking.
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return false;
+    }
+    let Some(vma) = KERNEL_VMAS.try_lock().and_then(|vmas| vmas.find(addr).copied()) else {
+        return false;
+    };
+    let permitted = (!error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+        || vma.flags.contains(PageTableFlags::WRITABLE))
+        && (!error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH)
+            || !vma.flags.contains(PageTableFlags::NO_EXECUTE))
+        && (!error_code.contains(PageFaultErrorCode::USER_MODE)
+            || vma.flags.contains(PageTableFlags::USER_ACCESSIBLE));
+    if vma.backing != Backing::Demand || !permitted {
+        return false;
+    }
+
+    let Some(mut memory) = KERNEL_MEMORY.try_lock() else {
+        return false;
+    };
+    let Some(KernelMemory { mapper, frame_allocator }) = memory.as_mut() else {
+        return false;
+    };
+    let Some(frame) = frame_allocator.allocate_frame() else {
+        return false;
+    };
+    let frame_ptr: *mut u8 = (mapper.phys_offset() + frame.start_address().as_u64()).as_mut_ptr();
+    unsafe { frame_ptr.write_bytes(0, Size4KiB::SIZE as usize) };
+
+    let page = Page::<Size4KiB>::containing_address(addr);
+    let flags = vma.flags | PageTableFlags::PRESENT;
+    match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+        Ok(flush) => {
+            flush.flush();
+            true
+        }
+        Err(_) => {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+            false
+        }
+    }
+}
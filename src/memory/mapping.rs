@@ -0,0 +1,289 @@
+use super::BitmapFrameAllocator;
+use core::arch::x86_64::__cpuid;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    instructions::tlb,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+        PageTableFlags, PhysFrame, Size1GiB, Size2MiB, Size4KiB, mapper::MapToError,
+        page_table::PageTableEntry,
+    },
+};
+
+/// The PAT bit of a huge page entry. It is part of the address bits, since
+/// bit 7, which selects the PAT entry in a 4 KiB page entry, marks the huge
+/// page itself.
+pub(super) const HUGE_PAGE_PAT: u64 = 1 << 12;
+
+/// Errors returned by the range mapping functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// A frame for a new page table could not be allocated.
+    FrameAllocationFailed,
+    /// The page containing the given address is already mapped.
+    PageAlreadyMapped(VirtAddr),
+    /// The page containing the given address is not mapped.
+    NotMapped(VirtAddr),
+    /// The start address, physical address or length is not 4 KiB aligned.
+    Unaligned,
+    /// The page containing the given address is a huge page, which the
+    /// operation does not support.
+    HugePage(VirtAddr),
+    /// The given address lies in the kernel half of an address space.
+    KernelHalf(VirtAddr),
+}
+
+/// Returns whether the CPU supports 1 GiB pages.
+pub fn gib_pages_supported() -> bool {
+    __cpuid(0x8000_0001).edx & (1 << 26) != 0
+}
+
+/// Maps the virtual range `start..start + len` to the physical range starting
+/// at `phys`, using the largest page size the alignment of both ranges allows.
+///
+/// 1 GiB pages are only used when the CPU supports them. If a page can't be
+/// mapped, the pages mapped so far are unmapped again before the error is
+/// returned.
+///
+/// This function is unsafe because the caller must guarantee that the physical
+/// range is not in use by anything else, e.g. as frames of the heap.
+pub unsafe fn map_range(
+    mapper: &mut OffsetPageTable,
+    start: VirtAddr,
+    phys: PhysAddr,
+    len: u64,
+    flags: PageTableFlags,
+    frame_allocator: &mut BitmapFrameAllocator,
+) -> Result<(), MapError> {
+    if !start.is_aligned(Size4KiB::SIZE) || !phys.is_aligned(Size4KiB::SIZE)
+        || !len.is_multiple_of(Size4KiB::SIZE)
+    {
+        return Err(MapError::Unaligned);
+    }
+
+    let use_gib_pages = gib_pages_supported();
+    let mut offset = 0;
+    while offset < len {
+        let virt = start + offset;
+        let frame_addr = phys + offset;
+        let fits = |size: u64| {
+            virt.is_aligned(size) && frame_addr.is_aligned(size) && len - offset >= size
+        };
+
+        let result = if use_gib_pages && fits(Size1GiB::SIZE) {
+            unsafe { map_page::<Size1GiB>(mapper, virt, frame_addr, flags, frame_allocator) }
+        } else if fits(Size2MiB::SIZE) {
+            unsafe { map_page::<Size2MiB>(mapper, virt, frame_addr, flags, frame_allocator) }
+        } else {
+            unsafe { map_page::<Size4KiB>(mapper, virt, frame_addr, flags, frame_allocator) }
+        };
+        match result {
+            Ok(size) => offset += size,
+            Err(err) => {
+                // the mapped pages are whole, so unmapping them splits nothing
+                unsafe { unmap_range(mapper, start, offset, frame_allocator) }
+                    .expect("failed to unmap a partially mapped range");
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+unsafe fn map_page<S: PageSize>(
+    mapper: &mut impl Mapper<S>,
+    virt: VirtAddr,
+    phys: PhysAddr,
+    flags: PageTableFlags,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<u64, MapError> {
+    let page = Page::<S>::containing_address(virt);
+    let frame = PhysFrame::<S>::containing_address(phys);
+    match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+        Ok(flush) => flush.flush(),
+        Err(MapToError::FrameAllocationFailed) => return Err(MapError::FrameAllocationFailed),
+        Err(_) => return Err(MapError::PageAlreadyMapped(virt)),
+    }
+    Ok(S::SIZE)
+}
+
+/// Changes the flags of all pages in `start..start + len`.
+///
+/// Huge pages that are only partially covered by the range are split into
+/// smaller pages first, so that the pages outside of the range keep their
+/// old flags. The `WRITABLE` and `USER_ACCESSIBLE` bits of the new flags are
+/// also set on the parent entries so that they take effect.
+///
+/// This function is unsafe because changing the flags of pages that are in
+/// use can break memory safety.
+pub unsafe fn protect_range(
+    mapper: &mut OffsetPageTable,
+    start: VirtAddr,
+    len: u64,
+    flags: PageTableFlags,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapError> {
+    let parent_flags = flags & (PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE);
+    let mut offset = 0;
+    while offset < len {
+        let addr = start + offset;
+        let (entry, size) =
+            unsafe { leaf_entry(mapper, addr, len - offset, parent_flags, frame_allocator)? };
+        let huge = entry.flags() & PageTableFlags::HUGE_PAGE;
+        entry.set_flags(flags | huge | PageTableFlags::PRESENT);
+        tlb::flush(addr);
+        offset += size;
+    }
+    Ok(())
+}
+
+/// Unmaps all pages in `start..start + len`, splitting huge pages that are
+/// only partially covered by the range.
+///
+/// The mapped frames are not deallocated, this is up to the caller. Level 1
+/// and level 2 page tables that no longer map anything are freed, see
+/// `free_empty_tables`.
+///
+/// This function is unsafe because the caller must guarantee that the range
+/// is no longer accessed.
+pub unsafe fn unmap_range(
+    mapper: &mut OffsetPageTable,
+    start: VirtAddr,
+    len: u64,
+    frame_allocator: &mut BitmapFrameAllocator,
+) -> Result<(), MapError> {
+    let mut offset = 0;
+    while offset < len {
+        let addr = start + offset;
+        let (entry, size) = unsafe {
+            leaf_entry(mapper, addr, len - offset, PageTableFlags::empty(), frame_allocator)?
+        };
+        entry.set_unused();
+        unsafe { free_empty_tables(mapper, addr, frame_allocator) };
+        tlb::flush(addr);
+        offset += size;
+    }
+    Ok(())
+}
+
+/// Frees the level 1 and level 2 tables on the way to `addr` that have no
+/// used entry left, starting with the lowest one.
+///
+/// Level 3 tables are kept, since the level 4 entries that point to them are
+/// shared between address spaces. Tables that the frame allocator does not
+/// manage, like the ones the bootloader created, are kept as well.
+unsafe fn free_empty_tables(
+    mapper: &mut OffsetPageTable,
+    addr: VirtAddr,
+    frame_allocator: &mut BitmapFrameAllocator,
+) {
+    let phys_offset = mapper.phys_offset();
+    let table_of = |entry: &PageTableEntry| -> *mut PageTable {
+        (phys_offset + entry.addr().as_u64()).as_mut_ptr()
+    };
+
+    let level_4_entry = &mapper.level_4_table()[addr.p4_index()];
+    if level_4_entry.is_unused() {
+        return;
+    }
+    let level_3_entry = unsafe { &mut (&mut *table_of(level_4_entry))[addr.p3_index()] };
+    if level_3_entry.is_unused() || level_3_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+        return;
+    }
+    let level_2_entry = unsafe { &mut (&mut *table_of(level_3_entry))[addr.p2_index()] };
+    for entry in [level_2_entry, level_3_entry] {
+        // an unmapped 2 MiB page leaves its own entry unused
+        if entry.is_unused() {
+            continue;
+        }
+        if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return;
+        }
+        let frame = PhysFrame::containing_address(entry.addr());
+        let table = unsafe { &*table_of(entry) };
+        if !frame_allocator.is_managed(frame) || table.iter().any(|e| !e.is_unused()) {
+            return;
+        }
+        entry.set_unused();
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    }
+}
+
+/// Returns the entry that maps `addr` together with the size of the page it
+/// maps.
+///
+/// Huge pages that start at `addr` and fit into `remaining` are returned as
+/// they are, all other huge pages on the way are split. `parent_flags` are
+/// added to every non-leaf entry.
+unsafe fn leaf_entry<'a>(
+    mapper: &'a mut OffsetPageTable,
+    addr: VirtAddr,
+    remaining: u64,
+    parent_flags: PageTableFlags,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(&'a mut PageTableEntry, u64), MapError> {
+    let phys_offset = mapper.phys_offset();
+    let indexes = [addr.p4_index(), addr.p3_index(), addr.p2_index(), addr.p1_index()];
+    // size of the page mapped by a huge entry at each level
+    let sizes = [0, Size1GiB::SIZE, Size2MiB::SIZE, Size4KiB::SIZE];
+
+    let mut table: *mut PageTable = mapper.level_4_table();
+    for level in 0..4 {
+        let entry = unsafe { &mut (&mut *table)[indexes[level]] };
+        if entry.is_unused() {
+            return Err(MapError::NotMapped(addr));
+        }
+        let huge = level > 0 && entry.flags().contains(PageTableFlags::HUGE_PAGE);
+        if level == 3 || huge {
+            let size = sizes[level];
+            if level == 3 || (addr.is_aligned(size) && remaining >= size) {
+                return Ok((entry, size));
+            }
+            unsafe { split_huge_page(entry, level, phys_offset, frame_allocator)? };
+        }
+        entry.set_flags(entry.flags() | parent_flags);
+        table = (phys_offset + entry.addr().as_u64()).as_mut_ptr();
+    }
+    unreachable!()
+}
+
+/// Replaces the huge page mapped by `entry` with a page table that maps the
+/// same physical memory with the same flags and memory type using the next
+/// smaller page size.
+///
+/// `level` is 1 for an entry of a level 3 table (1 GiB pages) and 2 for an
+/// entry of a level 2 table (2 MiB pages).
+unsafe fn split_huge_page(
+    entry: &mut PageTableEntry,
+    level: usize,
+    phys_offset: VirtAddr,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapError> {
+    let flags = entry.flags();
+    let pat = entry.addr().as_u64() & HUGE_PAGE_PAT;
+    let base = PhysAddr::new(entry.addr().as_u64() & !HUGE_PAGE_PAT);
+    // 2 MiB pages keep the PAT bit in the address, 4 KiB pages have it in
+    // bit 7, where huge pages have `HUGE_PAGE`
+    let (child_size, child_pat, child_flags) = if level == 1 {
+        (Size2MiB::SIZE, pat, flags)
+    } else {
+        let pat_flag = if pat != 0 { PageTableFlags::HUGE_PAGE } else { PageTableFlags::empty() };
+        (Size4KiB::SIZE, 0, (flags - PageTableFlags::HUGE_PAGE) | pat_flag)
+    };
+
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapError::FrameAllocationFailed)?;
+    let table_ptr: *mut PageTable = (phys_offset + frame.start_address().as_u64()).as_mut_ptr();
+    let table = unsafe { &mut *table_ptr };
+    for (i, child) in table.iter_mut().enumerate() {
+        child.set_addr(base + i as u64 * child_size + child_pat, child_flags);
+    }
+
+    // leave permission checks to the new entries
+    let parent_flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | (flags & PageTableFlags::USER_ACCESSIBLE);
+    entry.set_frame(frame, parent_flags);
+    Ok(())
+}
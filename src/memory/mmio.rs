@@ -0,0 +1,323 @@
+use super::{
+    KERNEL_MEMORY, KernelMemory,
+    mapping::{self, MapError},
+    vma::{Backing, KERNEL_VMAS, Owner, Vma, VmaError},
+};
+use core::{
+    arch::asm,
+    mem::{align_of, size_of},
+};
+use spin::Once;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    instructions::{interrupts, tlb},
+    registers::model_specific::Msr,
+    structures::paging::{
+        OffsetPageTable, PageSize, PageTableFlags, Size4KiB, Translate,
+        mapper::TranslateResult,
+    },
+};
+
+const IA32_PAT: u32 = 0x277;
+
+/// The PAT memory types, see the Intel SDM, Vol. 3A, 11.12.
+const PAT_UC: u64 = 0x00;
+const PAT_WC: u64 = 0x01;
+const PAT_WB: u64 = 0x06;
+const PAT_UC_MINUS: u64 = 0x07;
+
+/// The PAT entries as programmed by `init_pat`.
+///
+/// This equals the power-on default except for entry 1, which is changed
+/// from write-through to write-combining. The entry is selected by setting
+/// only `WRITE_THROUGH` in a page table entry.
+const PAT: [u64; 8] = [PAT_WB, PAT_WC, PAT_UC_MINUS, PAT_UC, PAT_WB, PAT_WC, PAT_UC_MINUS, PAT_UC];
+
+static PAT_INIT: Once<()> = Once::new();
+
+/// The memory type of an MMIO mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Caching {
+    /// Normal cached memory, e.g. for device memory that behaves like RAM.
+    WriteBack,
+    /// Writes are buffered and combined, reads are uncached. Suited for
+    /// framebuffers.
+    WriteCombining,
+    /// Strongly uncached, for device registers.
+    Uncached,
+}
+
+impl Caching {
+    /// Returns the page table flags that select the matching PAT entry.
+    fn flags(self) -> PageTableFlags {
+        match self {
+            Caching::WriteBack => PageTableFlags::empty(),
+            Caching::WriteCombining => PageTableFlags::WRITE_THROUGH,
+            Caching::Uncached => PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH,
+        }
+    }
+}
+
+/// The page table flags that select a PAT entry.
+const CACHING_FLAGS: PageTableFlags = PageTableFlags::NO_CACHE.union(PageTableFlags::WRITE_THROUGH);
+
+#[derive(Debug)]
+pub enum MmioError {
+    Vma(VmaError),
+    Map(MapError),
+    /// `memory::install` was not called yet.
+    MemoryNotInstalled,
+    /// The requested range is empty.
+    Empty,
+    /// The range overlaps the physical range of the given live mapping.
+    Overlap(Vma),
+    /// The physical memory mapping covers only part of the range or maps it
+    /// with different flags, so its memory type can't be changed and
+    /// restored as a whole.
+    InconsistentAlias,
+}
+
+/// Programs the PAT MSR with the entries of `PAT`.
+///
+/// This follows the sequence of the Intel SDM, Vol. 3A, 11.12.4: caches are
+/// written back before and after the change, and the TLB is flushed so that
+/// no cached translation keeps the old memory type of entry 1.
+fn init_pat() {
+    let value = PAT.iter().enumerate().fold(0, |value, (i, ty)| value | ty << (i * 8));
+    interrupts::without_interrupts(|| unsafe {
+        wbinvd();
+        Msr::new(IA32_PAT).write(value);
+        wbinvd();
+        tlb::flush_all();
+    });
+}
+
+/// Writes back and invalidates all caches.
+unsafe fn wbinvd() {
+    unsafe { asm!("wbinvd", options(nostack, preserves_flags)) };
+}
+
+/// Returns the flags of the pages that map `phys..phys + size` in the
+/// physical memory mapping, or `None` if the physical memory mapping does not
+/// cover the range.
+///
+/// Every page is checked, since restoring the alias on drop applies the same
+/// flags to the whole range.
+fn alias_flags(
+    mapper: &OffsetPageTable,
+    phys: PhysAddr,
+    size: u64,
+) -> Result<Option<PageTableFlags>, MmioError> {
+    let page_flags = |offset: u64| {
+        match mapper.translate(mapper.phys_offset() + (phys + offset).as_u64()) {
+            TranslateResult::Mapped { flags, .. } => Some(flags - PageTableFlags::HUGE_PAGE),
+            _ => None,
+        }
+    };
+    let first = page_flags(0);
+    let mut offsets = (Size4KiB::SIZE..size).step_by(Size4KiB::SIZE as usize);
+    if offsets.all(|offset| page_flags(offset) == first) {
+        Ok(first)
+    } else {
+        Err(MmioError::InconsistentAlias)
+    }
+}
+
+/// Returns the mapping whose physical range overlaps `phys..phys + size`.
+fn overlapping_mapping<'a>(
+    mut vmas: impl Iterator<Item = &'a Vma>,
+    phys: PhysAddr,
+    size: u64,
+) -> Option<&'a Vma> {
+    vmas.find(|vma| match vma.owner {
+        Owner::Mmio(start) => start < phys + size && phys < start + vma.size,
+        _ => false,
+    })
+}
+
+/// Maps the physical range `phys..phys + len` of a device into kernel
+/// virtual memory with the given caching attributes.
+///
+/// The range does not need to be page aligned. It is mapped into a newly
+/// reserved area, which is unmapped and released when the returned handle
+/// is dropped.
+///
+/// If the physical memory mapping covers the range, its pages there get the
+/// same memory type while the handle lives, since the CPU may otherwise
+/// cache the device memory through them. Huge pages of the physical memory
+/// mapping are split for that. Pages that are mapped by a live handle can't
+/// be mapped again, since dropping either handle restores their alias.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// physical range belongs to a device and is not usable RAM, and that no
+/// other mapping of it uses a conflicting memory type.
+pub unsafe fn map_mmio(phys: PhysAddr, len: u64, caching: Caching) -> Result<Mmio, MmioError> {
+    if len == 0 {
+        return Err(MmioError::Empty);
+    }
+    PAT_INIT.call_once(init_pat);
+
+    let phys_start = phys.align_down(Size4KiB::SIZE);
+    let size = (phys + len).align_up(Size4KiB::SIZE) - phys_start;
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | caching.flags();
+    // checked under the same lock as the reservation, so that no other
+    // mapping of the range can be created in between
+    let mut vmas = KERNEL_VMAS.lock();
+    if let Some(vma) = overlapping_mapping(vmas.iter(), phys_start, size) {
+        return Err(MmioError::Overlap(*vma));
+    }
+    let area_start = vmas
+        .reserve(size, Size4KiB::SIZE, Owner::Mmio(phys_start), flags, Backing::Eager)
+        .map_err(MmioError::Vma)?;
+    drop(vmas);
+
+    let mut memory = KERNEL_MEMORY.lock();
+    let Some(KernelMemory { mapper, frame_allocator }) = memory.as_mut() else {
+        KERNEL_VMAS.lock().release(area_start);
+        return Err(MmioError::MemoryNotInstalled);
+    };
+    let alias_flags = match alias_flags(mapper, phys_start, size) {
+        Ok(flags) => flags,
+        Err(err) => {
+            KERNEL_VMAS.lock().release(area_start);
+            return Err(err);
+        }
+    };
+    let result =
+        unsafe { mapping::map_range(mapper, area_start, phys_start, size, flags, frame_allocator) };
+    if let Err(err) = result {
+        KERNEL_VMAS.lock().release(area_start);
+        return Err(MmioError::Map(err));
+    }
+    if let Some(alias_flags) = alias_flags {
+        let alias_start = mapper.phys_offset() + phys_start.as_u64();
+        let new_flags = (alias_flags - CACHING_FLAGS) | caching.flags();
+        let result = unsafe {
+            mapping::protect_range(mapper, alias_start, size, new_flags, frame_allocator)
+        };
+        if let Err(err) = result {
+            unsafe {
+                let _ = mapping::protect_range(
+                    mapper,
+                    alias_start,
+                    size,
+                    alias_flags,
+                    frame_allocator,
+                );
+                mapping::unmap_range(mapper, area_start, size, frame_allocator)
+                    .expect("failed to unmap MMIO range");
+            }
+            KERNEL_VMAS.lock().release(area_start);
+            return Err(MmioError::Map(err));
+        }
+        // drop lines that were cached through the alias
+        unsafe { wbinvd() };
+    }
+
+    Ok(Mmio {
+        base: area_start + (phys - phys_start),
+        phys,
+        len,
+        caching,
+        area_start,
+        area_size: size,
+        alias_flags,
+    })
+}
+
+/// A mapped range of device memory, see `map_mmio`.
+///
+/// Dropping the handle unmaps the range. If `KERNEL_MEMORY` is locked at
+/// that point, e.g. because the handle is dropped in an interrupt handler,
+/// the range stays mapped and its area reserved instead.
+#[derive(Debug)]
+pub struct Mmio {
+    base: VirtAddr,
+    phys: PhysAddr,
+    len: u64,
+    caching: Caching,
+    area_start: VirtAddr,
+    area_size: u64,
+    /// The original flags of the range in the physical memory mapping, if
+    /// it covers the range.
+    alias_flags: Option<PageTableFlags>,
+}
+
+impl Mmio {
+    /// Returns the virtual address that `phys` is mapped to.
+    pub fn base(&self) -> VirtAddr {
+        self.base
+    }
+
+    pub fn phys(&self) -> PhysAddr {
+        self.phys
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn caching(&self) -> Caching {
+        self.caching
+    }
+
+    /// Returns a pointer to the given offset, checking that a `T` at that
+    /// offset is aligned and lies within the mapped range.
+    fn ptr<T>(&self, offset: u64) -> *mut T {
+        assert!(
+            offset + size_of::<T>() as u64 <= self.len,
+            "offset {:#x} is outside of the MMIO range",
+            offset
+        );
+        let addr = self.base + offset;
+        assert!(addr.is_aligned(align_of::<T>() as u64), "unaligned MMIO access");
+        addr.as_mut_ptr()
+    }
+
+    /// Reads a value at the given byte offset with a volatile read.
+    pub fn read<T: Copy>(&self, offset: u64) -> T {
+        unsafe { self.ptr::<T>(offset).read_volatile() }
+    }
+
+    /// Writes a value at the given byte offset with a volatile write.
+    pub fn write<T: Copy>(&self, offset: u64, value: T) {
+        unsafe { self.ptr::<T>(offset).write_volatile(value) }
+    }
+}
+
+impl Drop for Mmio {
+    fn drop(&mut self) {
+        let Some(mut memory) = KERNEL_MEMORY.try_lock() else {
+            return;
+        };
+        let KernelMemory { mapper, frame_allocator } =
+            memory.as_mut().expect("kernel memory not installed");
+        // the frames belong to the device, so they are not deallocated
+        unsafe { mapping::unmap_range(mapper, self.area_start, self.area_size, frame_allocator) }
+            .expect("failed to unmap MMIO range");
+        if let Some(alias_flags) = self.alias_flags {
+            let alias_start = mapper.phys_offset() + self.phys.align_down(Size4KiB::SIZE).as_u64();
+            unsafe {
+                // write back what was buffered before the alias caches again
+                wbinvd();
+                mapping::protect_range(
+                    mapper,
+                    alias_start,
+                    self.area_size,
+                    alias_flags,
+                    frame_allocator,
+                )
+            }
+            .expect("failed to restore the physical memory mapping");
+        }
+        drop(memory);
+        KERNEL_VMAS.lock().release(self.area_start);
+    }
+}
@@ -0,0 +1,151 @@
+use super::{
+    KERNEL_MEMORY, KernelMemory,
+    vma::{Backing, KERNEL_VMAS, Owner, VmaError},
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{
+    VirtAddr,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize,
+        PageTableFlags, Size4KiB, mapper::MapToError,
+    },
+};
+
+/// Size of the unmapped guard page below every kernel stack.
+const GUARD_SIZE: u64 = Size4KiB::SIZE;
+
+const STACK_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+/// Guard page of the stack the bootloader set up for the kernel, see
+/// `register_boot_stack`.
+static BOOT_STACK_GUARD: AtomicU64 = AtomicU64::new(0);
+
+/// A kernel stack with an unmapped guard page directly below it.
+#[derive(Debug)]
+pub struct KernelStack {
+    pub name: &'static str,
+    /// The lowest mapped address of the stack.
+    pub bottom: VirtAddr,
+    /// The address the stack pointer starts at.
+    pub top: VirtAddr,
+}
+
+#[derive(Debug)]
+pub enum StackError {
+    Vma(VmaError),
+    Map(MapToError<Size4KiB>),
+    /// `memory::install` was not called yet.
+    MemoryNotInstalled,
+}
+
+/// Allocates a stack of `size` bytes (rounded up to whole pages) in its own
+/// virtual memory area.
+///
+/// The area contains an additional guard page below the stack that is never
+/// mapped, so that an overflow causes a page fault that `guard_page_owner`
+/// can attribute to the stack.
+pub fn allocate(name: &'static str, size: u64) -> Result<KernelStack, StackError> {
+    let size = size.next_multiple_of(Size4KiB::SIZE);
+    let area_start = KERNEL_VMAS
+        .lock()
+        .reserve(
+            GUARD_SIZE + size,
+            Size4KiB::SIZE,
+            Owner::KernelStack(name),
+            STACK_FLAGS,
+            Backing::Eager,
+        )
+        .map_err(StackError::Vma)?;
+    let stack = KernelStack {
+        name,
+        bottom: area_start + GUARD_SIZE,
+        top: area_start + GUARD_SIZE + size,
+    };
+
+    let mut memory = KERNEL_MEMORY.lock();
+    let Some(KernelMemory { mapper, frame_allocator }) = memory.as_mut() else {
+        KERNEL_VMAS.lock().release(area_start);
+        return Err(StackError::MemoryNotInstalled);
+    };
+    let pages = Page::range(
+        Page::<Size4KiB>::containing_address(stack.bottom),
+        Page::containing_address(stack.top),
+    );
+    for page in pages {
+        let result = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)
+            .and_then(|frame| unsafe {
+                mapper.map_to(page, frame, STACK_FLAGS, frame_allocator)
+            });
+        match result {
+            Ok(flush) => flush.flush(),
+            Err(err) => {
+                unsafe { unmap_stack(&stack, mapper, frame_allocator) };
+                KERNEL_VMAS.lock().release(area_start);
+                return Err(StackError::Map(err));
+            }
+        }
+    }
+    Ok(stack)
+}
+
+/// Unmaps the given stack, frees its frames and releases its area.
+///
+/// This function is unsafe because the caller must guarantee that the stack
+/// is no longer in use.
+pub unsafe fn free(stack: KernelStack) {
+    let mut memory = KERNEL_MEMORY.lock();
+    let KernelMemory { mapper, frame_allocator } =
+        memory.as_mut().expect("kernel memory not installed");
+    unsafe { unmap_stack(&stack, mapper, frame_allocator) };
+    KERNEL_VMAS.lock().release(stack.bottom - GUARD_SIZE);
+}
+
+unsafe fn unmap_stack(
+    stack: &KernelStack,
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameDeallocator<Size4KiB>,
+) {
+    let pages = Page::range(
+        Page::<Size4KiB>::containing_address(stack.bottom),
+        Page::containing_address(stack.top),
+    );
+    for page in pages {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            unsafe { frame_allocator.deallocate_frame(frame) };
+        }
+    }
+}
+
+/// Remembers the guard page below the stack that the bootloader set up for
+/// the kernel, so that overflows of it are reported as well.
+///
+/// Must be called on the boot stack.
+pub fn register_boot_stack(mapper: &OffsetPageTable) {
+    let local = 0u8;
+    let stack_pointer = VirtAddr::from_ptr(&raw const local);
+    let mut page = Page::<Size4KiB>::containing_address(stack_pointer);
+    // the bootloader leaves the page below the stack unmapped
+    while mapper.translate_page(page).is_ok() {
+        page -= 1;
+    }
+    BOOT_STACK_GUARD.store(page.start_address().as_u64(), Ordering::Relaxed);
+}
+
+/// Returns the name of the stack whose guard page contains the given address.
+pub fn guard_page_owner(addr: VirtAddr) -> Option<&'static str> {
+    let boot_guard = BOOT_STACK_GUARD.load(Ordering::Relaxed);
+    if boot_guard != 0 && (boot_guard..boot_guard + GUARD_SIZE).contains(&addr.as_u64()) {
+        return Some("boot");
+    }
+    let vmas = KERNEL_VMAS.try_lock()?;
+    let vma = vmas.find(addr)?;
+    match vma.owner {
+        Owner::KernelStack(name) if addr < vma.start + GUARD_SIZE => Some(name),
+        _ => None,
+    }
+}
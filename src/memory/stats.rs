@@ -0,0 +1,187 @@
+use super::KERNEL_MEMORY;
+use crate::allocator;
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::fmt;
+
+const FRAME_SIZE: u64 = 4096;
+
+/// Number of distinct `MemoryRegionType`s a summary can hold.
+const MAX_REGION_TYPES: usize = 16;
+
+/// The regions of one type in the memory map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionTypeSummary {
+    pub region_type: MemoryRegionType,
+    pub regions: usize,
+    pub bytes: u64,
+}
+
+/// The bootloader's memory map, summarized by region type.
+///
+/// The summary is stored inline, so it can be created before the heap is
+/// initialized. Regions of types beyond the first `MAX_REGION_TYPES` are
+/// counted together as other regions.
+#[derive(Debug, Clone)]
+pub struct MemoryMapSummary {
+    types: [Option<RegionTypeSummary>; MAX_REGION_TYPES],
+    len: usize,
+    other_regions: usize,
+    other_bytes: u64,
+}
+
+impl MemoryMapSummary {
+    /// Summarizes the given memory map, keeping the types in the order they
+    /// first appear in.
+    pub fn new(memory_map: &MemoryMap) -> Self {
+        let mut summary = MemoryMapSummary {
+            types: [None; MAX_REGION_TYPES],
+            len: 0,
+            other_regions: 0,
+            other_bytes: 0,
+        };
+        for region in memory_map.iter() {
+            let bytes = region.range.end_addr() - region.range.start_addr();
+            let position = summary
+                .iter()
+                .position(|t| t.region_type == region.region_type);
+            let index = match position {
+                Some(index) => index,
+                None if summary.len == MAX_REGION_TYPES => {
+                    summary.other_regions += 1;
+                    summary.other_bytes += bytes;
+                    continue;
+                }
+                None => {
+                    summary.types[summary.len] = Some(RegionTypeSummary {
+                        region_type: region.region_type,
+                        regions: 0,
+                        bytes: 0,
+                    });
+                    summary.len += 1;
+                    summary.len - 1
+                }
+            };
+            let entry = summary.types[index].as_mut().unwrap();
+            entry.regions += 1;
+            entry.bytes += bytes;
+        }
+        summary
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RegionTypeSummary> {
+        self.types[..self.len].iter().flatten()
+    }
+
+    /// Returns the summary of the given region type, if the map contains it.
+    pub fn get(&self, region_type: MemoryRegionType) -> Option<&RegionTypeSummary> {
+        self.iter().find(|t| t.region_type == region_type)
+    }
+
+    /// Returns the number of regions and bytes of the types that did not fit
+    /// into the summary.
+    pub fn other(&self) -> (usize, u64) {
+        (self.other_regions, self.other_bytes)
+    }
+
+    /// Returns the number of bytes covered by all regions.
+    pub fn total_bytes(&self) -> u64 {
+        self.iter().map(|t| t.bytes).sum::<u64>() + self.other_bytes
+    }
+}
+
+impl fmt::Display for MemoryMapSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:<18} {:>8} {:>12}", "Region type", "Regions", "Size")?;
+        for t in self.iter() {
+            // format the type first so that the width applies
+            let mut name = Buffer::<18>::new();
+            let _ = fmt::write(&mut name, format_args!("{:?}", t.region_type));
+            writeln!(f, "{:<18} {:>8} {:>8} KiB", name.as_str(), t.regions, t.bytes / 1024)?;
+        }
+        if self.other_regions > 0 {
+            let (regions, bytes) = self.other();
+            writeln!(f, "{:<18} {:>8} {:>8} KiB", "Other", regions, bytes / 1024)?;
+        }
+        write!(f, "{:<18} {:>8} {:>8} KiB", "Total", "", self.total_bytes() / 1024)
+    }
+}
+
+/// The current usage of physical memory and of the heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemInfo {
+    /// Frames managed by the frame allocator.
+    pub usable_frames: usize,
+    pub free_frames: usize,
+    pub used_frames: usize,
+    /// Bytes currently mapped for the heap.
+    pub heap_size: usize,
+    /// Bytes the heap may grow to.
+    pub heap_limit: usize,
+}
+
+impl MemInfo {
+    pub fn usable_bytes(&self) -> u64 {
+        self.usable_frames as u64 * FRAME_SIZE
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        self.free_frames as u64 * FRAME_SIZE
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.used_frames as u64 * FRAME_SIZE
+    }
+}
+
+impl fmt::Display for MemInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "MemTotal:  {:>10} KiB", self.usable_bytes() / 1024)?;
+        writeln!(f, "MemFree:   {:>10} KiB", self.free_bytes() / 1024)?;
+        writeln!(f, "MemUsed:   {:>10} KiB", self.used_bytes() / 1024)?;
+        writeln!(f, "HeapSize:  {:>10} KiB", self.heap_size / 1024)?;
+        write!(f, "HeapLimit: {:>10} KiB", self.heap_limit / 1024)
+    }
+}
+
+/// Returns the current memory usage, or `None` if `memory::install` was not
+/// called yet.
+pub fn meminfo() -> Option<MemInfo> {
+    // the heap area is locked while the heap grows, which takes
+    // `KERNEL_MEMORY` afterwards, so it must not be locked the other way round
+    let heap_size = allocator::heap_size();
+    let heap_limit = allocator::heap_limit();
+    let memory = KERNEL_MEMORY.lock();
+    let frames = &memory.as_ref()?.frame_allocator;
+    Some(MemInfo {
+        usable_frames: frames.usable_frames(),
+        free_frames: frames.free_frames(),
+        used_frames: frames.used_frames(),
+        heap_size,
+        heap_limit,
+    })
+}
+
+/// A fixed-size string buffer that silently truncates.
+struct Buffer<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Buffer<N> {
+    fn new() -> Self {
+        Buffer { bytes: [0; N], len: 0 }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("?")
+    }
+}
+
+impl<const N: usize> fmt::Write for Buffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = s.len().min(N - self.len);
+        self.bytes[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        Ok(())
+    }
+}
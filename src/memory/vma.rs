@@ -0,0 +1,281 @@
+use core::fmt;
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr, structures::paging::PageTableFlags};
+
+/// Start of the virtual range that kernel regions are carved from.
+///
+/// The range covers exactly one level 4 entry (index 136), so it never
+/// collides with the kernel image, the bootloader stack or the physical
+/// memory mapping.
+pub const KERNEL_VIRT_START: u64 = 0x_4400_0000_0000;
+/// End (exclusive) of the virtual range that kernel regions are carved from.
+pub const KERNEL_VIRT_END: u64 = 0x_4480_0000_0000;
+
+/// Maximum number of regions a `VmaManager` can hold.
+const MAX_VMAS: usize = 128;
+
+/// The virtual memory areas of the kernel.
+pub static KERNEL_VMAS: Mutex<VmaManager> =
+    Mutex::new(VmaManager::new(KERNEL_VIRT_START, KERNEL_VIRT_END));
+
+/// Describes what a virtual memory area is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Owner {
+    Heap,
+    KernelStack(&'static str),
+    /// Device memory mapped by `map_mmio`, starting at the given physical
+    /// address.
+    Mmio(PhysAddr),
+    Task(u64),
+    Other(&'static str),
+}
+
+/// Describes how the pages of a virtual memory area get mapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    /// The owner maps the pages itself.
+    Eager,
+    /// Pages are mapped to zeroed frames on first access by the page fault
+    /// handler.
+    Demand,
+}
+
+/// A reserved range of virtual memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vma {
+    pub start: VirtAddr,
+    pub size: u64,
+    pub owner: Owner,
+    /// The flags that pages of this area are mapped with.
+    pub flags: PageTableFlags,
+    pub backing: Backing,
+}
+
+impl Vma {
+    /// Returns the first address after this area.
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    /// Returns whether the given address lies within this area.
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    /// The requested range overlaps the given existing area.
+    Overlap(Vma),
+    /// The requested range lies (partly) outside of the managed window.
+    OutsideWindow,
+    /// There is no gap large enough for the requested size.
+    OutOfSpace,
+    /// The maximum number of areas is reached.
+    TooManyAreas,
+    /// The requested alignment is not a power of two.
+    InvalidAlignment(u64),
+}
+
+/// Keeps track of the reserved ranges within a window of virtual memory.
+///
+/// Areas never overlap, so keeping them sorted by start address is enough to
+/// answer both point queries and overlap checks with a binary search. They are
+/// stored in a fixed-size array because the heap itself is carved from here.
+pub struct VmaManager {
+    window_start: u64,
+    window_end: u64,
+    areas: [Option<Vma>; MAX_VMAS],
+    len: usize,
+}
+
+impl VmaManager {
+    /// Creates an empty manager for the window `start..end`.
+    pub const fn new(start: u64, end: u64) -> Self {
+        VmaManager {
+            window_start: start,
+            window_end: end,
+            areas: [None; MAX_VMAS],
+            len: 0,
+        }
+    }
+
+    /// Returns an iterator over all areas, ordered by start address.
+    pub fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.areas[..self.len].iter().flatten()
+    }
+
+    fn area(&self, index: usize) -> &Vma {
+        self.areas[index].as_ref().unwrap()
+    }
+
+    /// Returns the index of the first area that ends after `addr`.
+    fn search(&self, addr: u64) -> usize {
+        self.areas[..self.len].partition_point(|a| a.unwrap().end().as_u64() <= addr)
+    }
+
+    /// Returns the area containing the given address.
+    pub fn find(&self, addr: VirtAddr) -> Option<&Vma> {
+        let index = self.search(addr.as_u64());
+        (index < self.len)
+            .then(|| self.area(index))
+            .filter(|area| area.contains(addr))
+    }
+
+    /// Reserves `size` bytes anywhere in the window, aligned to `align`,
+    /// which must be a power of two.
+    ///
+    /// Returns the start address of the new area.
+    pub fn reserve(
+        &mut self,
+        size: u64,
+        align: u64,
+        owner: Owner,
+        flags: PageTableFlags,
+        backing: Backing,
+    ) -> Result<VirtAddr, VmaError> {
+        if !align.is_power_of_two() {
+            return Err(VmaError::InvalidAlignment(align));
+        }
+        // first fit: look at the gap before each area and after the last one
+        let mut gap_start = self.window_start;
+        for index in 0..=self.len {
+            let gap_end = if index < self.len {
+                self.area(index).start.as_u64()
+            } else {
+                self.window_end
+            };
+            let start = gap_start.next_multiple_of(align);
+            if start.checked_add(size).is_some_and(|end| end <= gap_end) {
+                let vma = Vma { start: VirtAddr::new(start), size, owner, flags, backing };
+                self.insert(index, vma)?;
+                return Ok(vma.start);
+            }
+            if index < self.len {
+                gap_start = self.area(index).end().as_u64();
+            }
+        }
+        Err(VmaError::OutOfSpace)
+    }
+
+    /// Reserves the range `start..start + size`.
+    pub fn reserve_at(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        owner: Owner,
+        flags: PageTableFlags,
+        backing: Backing,
+    ) -> Result<(), VmaError> {
+        let end = start.as_u64().checked_add(size).ok_or(VmaError::OutsideWindow)?;
+        if start.as_u64() < self.window_start || end > self.window_end {
+            return Err(VmaError::OutsideWindow);
+        }
+        let index = self.search(start.as_u64());
+        if index < self.len && self.area(index).start.as_u64() < end {
+            return Err(VmaError::Overlap(*self.area(index)));
+        }
+        self.insert(index, Vma { start, size, owner, flags, backing })
+    }
+
+    fn insert(&mut self, index: usize, vma: Vma) -> Result<(), VmaError> {
+        if self.len == MAX_VMAS {
+            return Err(VmaError::TooManyAreas);
+        }
+        self.areas[index..=self.len].rotate_right(1);
+        self.areas[index] = Some(vma);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes the area starting at `start` and returns it.
+    pub fn release(&mut self, start: VirtAddr) -> Option<Vma> {
+        let index = self.search(start.as_u64());
+        if index == self.len || self.area(index).start != start {
+            return None;
+        }
+        let vma = self.areas[index].take();
+        self.areas[index..self.len].rotate_left(1);
+        self.len -= 1;
+        vma
+    }
+
+    /// Writes a table of all areas to the given writer.
+    pub fn dump(&self, writer: &mut impl fmt::Write) -> fmt::Result {
+        writeln!(
+            writer,
+            "VMAs {:#x}-{:#x} ({} areas)",
+            self.window_start, self.window_end, self.len
+        )?;
+        for vma in self.iter() {
+            writeln!(
+                writer,
+                "  {:#014x}-{:#014x} {:>8} KiB {:?} {:?} {:?}",
+                vma.start.as_u64(),
+                vma.end().as_u64(),
+                vma.size / 1024,
+                vma.owner,
+                vma.backing,
+                vma.flags
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn test_reserve(vmas: &mut VmaManager, size: u64, align: u64, owner: Owner) -> VirtAddr {
+    vmas.reserve(size, align, owner, PageTableFlags::PRESENT, Backing::Eager).unwrap()
+}
+
+#[cfg(test)]
+const MMIO: Owner = Owner::Mmio(PhysAddr::zero());
+
+#[cfg(test)]
+fn test_reserve_at(vmas: &mut VmaManager, start: u64, size: u64, owner: Owner)
+    -> Result<(), VmaError>
+{
+    vmas.reserve_at(VirtAddr::new(start), size, owner, PageTableFlags::PRESENT, Backing::Eager)
+}
+
+#[test_case]
+fn reserve_finds_aligned_gaps() {
+    let mut vmas = VmaManager::new(0x10_0000, 0x20_0000);
+    let a = test_reserve(&mut vmas, 0x1000, 0x1000, Owner::Heap);
+    let b = test_reserve(&mut vmas, 0x1000, 0x1_0000, MMIO);
+    assert_eq!(a.as_u64(), 0x10_0000);
+    assert_eq!(b.as_u64(), 0x11_0000);
+    // the gap between both areas is used first
+    let c = test_reserve(&mut vmas, 0x2000, 0x1000, Owner::Other("test"));
+    assert_eq!(c.as_u64(), 0x10_1000);
+    assert_eq!(vmas.find(VirtAddr::new(0x10_2fff)).unwrap().start, c);
+    assert!(vmas.find(VirtAddr::new(0x10_3000)).is_none());
+}
+
+#[test_case]
+fn overlapping_reservations_are_rejected() {
+    let mut vmas = VmaManager::new(0x10_0000, 0x20_0000);
+    test_reserve_at(&mut vmas, 0x10_4000, 0x4000, Owner::Heap).unwrap();
+    assert!(matches!(
+        test_reserve_at(&mut vmas, 0x10_7000, 0x2000, MMIO),
+        Err(VmaError::Overlap(Vma { owner: Owner::Heap, .. }))
+    ));
+    assert!(test_reserve_at(&mut vmas, 0x10_2000, 0x2000, MMIO).is_ok());
+    assert_eq!(
+        test_reserve_at(&mut vmas, 0x1f_f000, 0x2000, MMIO),
+        Err(VmaError::OutsideWindow)
+    );
+    assert_eq!(vmas.release(VirtAddr::new(0x10_4000)).unwrap().owner, Owner::Heap);
+    assert!(test_reserve_at(&mut vmas, 0x10_7000, 0x2000, MMIO).is_ok());
+}
+
+#[test_case]
+fn invalid_alignments_are_rejected() {
+    let mut vmas = VmaManager::new(0x10_0000, 0x20_0000);
+    for align in [0, 0x3000] {
+        assert_eq!(
+            vmas.reserve(0x1000, align, Owner::Heap, PageTableFlags::PRESENT, Backing::Eager),
+            Err(VmaError::InvalidAlignment(align))
+        );
+    }
+}
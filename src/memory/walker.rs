@@ -0,0 +1,235 @@
+use super::mapping::HUGE_PAGE_PAT;
+use core::fmt;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    registers::control::Cr3,
+    structures::paging::{OffsetPageTable, PageTable, PageTableFlags, page_table::PageTableEntry},
+};
+
+/// Size of the page mapped by a leaf entry at each level, indexed by level.
+const PAGE_SIZES: [u64; 5] = [0, 4 << 10, 2 << 20, 1 << 30, 0];
+
+/// Flags that are ignored when merging mapped ranges, because the CPU updates
+/// them on access or because they only describe the page size.
+const IGNORED_FLAGS: PageTableFlags = PageTableFlags::ACCESSED
+    .union(PageTableFlags::DIRTY)
+    .union(PageTableFlags::HUGE_PAGE);
+
+/// The result of translating a virtual address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Translation {
+    pub addr: VirtAddr,
+    pub phys: PhysAddr,
+    /// The level of the table that contains the leaf entry, i.e. 1 for a
+    /// 4 KiB page, 2 for a 2 MiB page and 3 for a 1 GiB page.
+    pub level: usize,
+    pub page_size: u64,
+    /// The flags of the leaf entry.
+    pub flags: PageTableFlags,
+    /// The permissions that apply to the access, combined from all levels:
+    /// `WRITABLE` and `USER_ACCESSIBLE` are only set if every entry on the way
+    /// sets them, `NO_EXECUTE` is set if any entry sets it.
+    pub effective_flags: PageTableFlags,
+}
+
+impl fmt::Display for Translation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:#x} -> {:#x} ({} KiB page at level {}) {:?}",
+            self.addr.as_u64(),
+            self.phys.as_u64(),
+            self.page_size / 1024,
+            self.level,
+            self.effective_flags
+        )
+    }
+}
+
+/// The translation failed because the entry at the given level is unused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotMapped {
+    pub level: usize,
+}
+
+/// A range of virtual memory that is mapped to contiguous physical memory
+/// with the same effective flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedRange {
+    pub start: VirtAddr,
+    pub phys: PhysAddr,
+    pub size: u64,
+    pub effective_flags: PageTableFlags,
+}
+
+impl MappedRange {
+    /// Returns the first address after this range.
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+}
+
+/// Walks page tables through the physical memory mapping, without modifying
+/// them.
+pub struct PageTableWalker<'a> {
+    level_4_table: &'a PageTable,
+    phys_offset: VirtAddr,
+}
+
+impl<'a> PageTableWalker<'a> {
+    /// Creates a walker for the page tables below the given level 4 table.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// complete physical memory is mapped at `phys_offset`.
+    pub unsafe fn new(level_4_table: &'a PageTable, phys_offset: VirtAddr) -> Self {
+        PageTableWalker { level_4_table, phys_offset }
+    }
+
+    /// Creates a walker for the page tables of the given mapper.
+    pub fn from_mapper(mapper: &'a mut OffsetPageTable) -> Self {
+        let phys_offset = mapper.phys_offset();
+        PageTableWalker { level_4_table: mapper.level_4_table(), phys_offset }
+    }
+
+    /// Translates the given address and reports how it was resolved.
+    pub fn translate(&self, addr: VirtAddr) -> Result<Translation, NotMapped> {
+        let indexes = [addr.p1_index(), addr.p2_index(), addr.p3_index(), addr.p4_index()];
+        let mut table = self.level_4_table;
+        let mut effective_flags = Self::initial_flags();
+        for level in (1..=4).rev() {
+            let entry = &table[indexes[level - 1]];
+            if entry.is_unused() {
+                return Err(NotMapped { level });
+            }
+            effective_flags = Self::combine(effective_flags, entry.flags());
+            if level == 1 || (level < 4 && entry.flags().contains(PageTableFlags::HUGE_PAGE)) {
+                let page_size = PAGE_SIZES[level];
+                return Ok(Translation {
+                    addr,
+                    phys: Self::leaf_addr(entry, level) + (addr.as_u64() & (page_size - 1)),
+                    level,
+                    page_size,
+                    flags: entry.flags(),
+                    effective_flags,
+                });
+            }
+            table = self.table_at(entry.addr());
+        }
+        unreachable!()
+    }
+
+    /// Calls `f` for every mapped range, in ascending address order.
+    ///
+    /// Adjacent pages are merged into one range if they map contiguous
+    /// physical memory with the same effective flags. No memory is allocated,
+    /// so this can be used while the kernel memory is locked.
+    pub fn for_each_range(&self, mut f: impl FnMut(MappedRange)) {
+        let mut current: Option<MappedRange> = None;
+        self.walk(self.level_4_table, 4, 0, Self::initial_flags(), &mut |range| {
+            match current.as_mut() {
+                Some(c) if c.end() == range.start
+                    && c.phys + c.size == range.phys
+                    && c.effective_flags == range.effective_flags =>
+                {
+                    c.size += range.size;
+                }
+                _ => {
+                    if let Some(c) = current.replace(range) {
+                        f(c);
+                    }
+                }
+            }
+        });
+        if let Some(c) = current {
+            f(c);
+        }
+    }
+
+    fn walk(
+        &self,
+        table: &PageTable,
+        level: usize,
+        base: u64,
+        parent_flags: PageTableFlags,
+        f: &mut impl FnMut(MappedRange),
+    ) {
+        for (index, entry) in table.iter().enumerate() {
+            if entry.is_unused() {
+                continue;
+            }
+            let shift = 12 + 9 * (level - 1);
+            // sign-extends addresses in the upper half
+            let start = VirtAddr::new_truncate(base | (index as u64) << shift);
+            let flags = Self::combine(parent_flags, entry.flags());
+            if level == 1 || (level < 4 && entry.flags().contains(PageTableFlags::HUGE_PAGE)) {
+                f(MappedRange {
+                    start,
+                    phys: Self::leaf_addr(entry, level),
+                    size: PAGE_SIZES[level],
+                    effective_flags: flags - IGNORED_FLAGS,
+                });
+            } else {
+                self.walk(self.table_at(entry.addr()), level - 1, start.as_u64(), flags, f);
+            }
+        }
+    }
+
+    /// Writes a table of all mapped ranges to the given writer.
+    pub fn dump(&self, writer: &mut impl fmt::Write) -> fmt::Result {
+        let mut result = Ok(());
+        self.for_each_range(|range| {
+            if result.is_ok() {
+                result = writeln!(
+                    writer,
+                    "  {:#018x}-{:#018x} -> {:#014x} {:>10} KiB {:?}",
+                    range.start.as_u64(),
+                    range.end().as_u64(),
+                    range.phys.as_u64(),
+                    range.size / 1024,
+                    range.effective_flags
+                );
+            }
+        });
+        result
+    }
+
+    fn initial_flags() -> PageTableFlags {
+        PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE
+    }
+
+    /// Combines the permissions of a parent entry with those of its child.
+    fn combine(parent: PageTableFlags, child: PageTableFlags) -> PageTableFlags {
+        let restricting = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        (child - restricting)
+            | (parent & child & restricting)
+            | (parent & PageTableFlags::NO_EXECUTE)
+    }
+
+    /// Returns the address of the frame mapped by a leaf entry, without the
+    /// PAT bit that huge page entries keep in the lowest address bit.
+    fn leaf_addr(entry: &PageTableEntry, level: usize) -> PhysAddr {
+        if level == 1 {
+            entry.addr()
+        } else {
+            PhysAddr::new(entry.addr().as_u64() & !HUGE_PAGE_PAT)
+        }
+    }
+
+    fn table_at(&self, phys: PhysAddr) -> &'a PageTable {
+        unsafe { &*(self.phys_offset + phys.as_u64()).as_ptr() }
+    }
+}
+
+impl PageTableWalker<'static> {
+    /// Creates a walker for the active page table, e.g. for debugging code
+    /// that has no access to the kernel memory.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// complete physical memory is mapped at `phys_offset` and that the page
+    /// tables are not modified while the walker is in use.
+    pub unsafe fn active(phys_offset: VirtAddr) -> Self {
+        let (frame, _) = Cr3::read();
+        let table = unsafe { &*(phys_offset + frame.start_address().as_u64()).as_ptr() };
+        PageTableWalker { level_4_table: table, phys_offset }
+    }
+}
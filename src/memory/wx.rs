@@ -0,0 +1,116 @@
+use super::{
+    mapping::{self, MapError},
+    walker::{MappedRange, PageTableWalker},
+};
+use x86_64::{
+    VirtAddr,
+    registers::model_specific::{Efer, EferFlags},
+    structures::paging::{FrameAllocator, OffsetPageTable, PageSize, PageTableFlags, Size4KiB},
+};
+
+const PT_LOAD: u32 = 1;
+const PT_GNU_RELRO: u32 = 0x6474_e552;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+unsafe extern "C" {
+    /// The ELF header of the kernel, defined by the linker. It is part of the
+    /// first loaded segment, so the program headers are mapped as well.
+    static __ehdr_start: u8;
+}
+
+/// A program header of a 64-bit ELF file.
+#[repr(C)]
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    virt_addr: u64,
+    phys_addr: u64,
+    file_size: u64,
+    mem_size: u64,
+    align: u64,
+}
+
+/// Sets `EFER.NXE`, which makes the CPU honor the `NO_EXECUTE` flag.
+pub fn enable_nxe() {
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE)) };
+}
+
+/// Returns the program headers of the running kernel.
+fn program_headers() -> &'static [ProgramHeader] {
+    let header = &raw const __ehdr_start;
+    unsafe {
+        let ph_offset = header.add(0x20).cast::<u64>().read_unaligned();
+        let ph_count = header.add(0x38).cast::<u16>().read_unaligned();
+        let first = header.add(ph_offset as usize).cast::<ProgramHeader>();
+        core::slice::from_raw_parts(first, ph_count as usize)
+    }
+}
+
+/// Remaps all pages so that none of them is both writable and executable.
+///
+/// The loaded segments of the kernel get the permissions of their ELF flags:
+/// text is read-only and executable, read-only data is read-only and no
+/// data is executable. The `GNU_RELRO` part of the data is made read-only as
+/// well. All other mappings that are still writable and executable, like the
+/// boot stack, the physical memory mapping or the identity mapped bootloader
+/// pages, are made non-executable.
+///
+/// This function is unsafe because the caller must guarantee that no code
+/// outside of the kernel's text segment is executed afterwards.
+pub unsafe fn enforce(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapError> {
+    enable_nxe();
+
+    for header in program_headers().iter().filter(|h| h.kind == PT_LOAD) {
+        let mut flags = PageTableFlags::PRESENT;
+        if header.flags & PF_W != 0 {
+            flags |= PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        } else if header.flags & PF_X == 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        unsafe { protect_segment(mapper, header, flags, frame_allocator)? };
+    }
+    for header in program_headers().iter().filter(|h| h.kind == PT_GNU_RELRO) {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+        unsafe { protect_segment(mapper, header, flags, frame_allocator)? };
+    }
+
+    while let Err(range) = check(mapper) {
+        let flags = range.effective_flags | PageTableFlags::NO_EXECUTE;
+        unsafe { mapping::protect_range(mapper, range.start, range.size, flags, frame_allocator)? };
+    }
+    Ok(())
+}
+
+/// Changes the flags of all pages that contain a part of the given segment.
+unsafe fn protect_segment(
+    mapper: &mut OffsetPageTable,
+    header: &ProgramHeader,
+    flags: PageTableFlags,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapError> {
+    let start = VirtAddr::new(header.virt_addr).align_down(Size4KiB::SIZE);
+    let end = VirtAddr::new(header.virt_addr + header.mem_size).align_up(Size4KiB::SIZE);
+    unsafe { mapping::protect_range(mapper, start, end - start, flags, frame_allocator) }
+}
+
+/// Checks that no page is both writable and executable.
+///
+/// Returns the first offending range otherwise.
+pub fn check(mapper: &mut OffsetPageTable) -> Result<(), MappedRange> {
+    let mut offending = None;
+    PageTableWalker::from_mapper(mapper).for_each_range(|range| {
+        let flags = range.effective_flags;
+        if offending.is_none()
+            && flags.contains(PageTableFlags::WRITABLE)
+            && !flags.contains(PageTableFlags::NO_EXECUTE)
+        {
+            offending = Some(range);
+        }
+    });
+    offending.map_or(Ok(()), Err)
+}
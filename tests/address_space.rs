@@ -0,0 +1,100 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::memory::{
+    address_space::PRIVATE_START, mapping::MapError, AddressSpace, KernelMemory, KERNEL_MEMORY,
+};
+use x86_64::{
+    VirtAddr,
+    registers::control::Cr3,
+    structures::paging::{FrameAllocator, Page, PageTableFlags, Translate},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os::test_boot(boot_info);
+
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+const FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+/// Creates an address space with a fresh frame mapped at `PRIVATE_START`.
+fn space_with_page() -> AddressSpace {
+    let mut memory = KERNEL_MEMORY.lock();
+    let KernelMemory { mapper, frame_allocator } = memory.as_mut().unwrap();
+    let mut space = AddressSpace::new(mapper, frame_allocator).unwrap();
+    let page = Page::containing_address(VirtAddr::new(PRIVATE_START));
+    let frame = frame_allocator.allocate_frame().unwrap();
+    unsafe { space.map(page, frame, FLAGS, frame_allocator).unwrap() };
+    space
+}
+
+fn destroy(space: AddressSpace) {
+    let mut memory = KERNEL_MEMORY.lock();
+    unsafe { space.destroy(&mut memory.as_mut().unwrap().frame_allocator) };
+}
+
+fn free_frames() -> usize {
+    KERNEL_MEMORY.lock().as_ref().unwrap().frame_allocator.free_frames()
+}
+
+#[test_case]
+fn private_pages_are_only_visible_when_active() {
+    let space = space_with_page();
+    let addr = VirtAddr::new(PRIVATE_START);
+    let kernel_mapped = |addr| {
+        KERNEL_MEMORY.lock().as_ref().unwrap().mapper.translate_addr(addr).is_some()
+    };
+    assert!(!kernel_mapped(addr));
+
+    let kernel_frame = unsafe { space.activate() };
+    assert!(space.is_active());
+    // the heap lives in the shared kernel half
+    let boxed = Box::new(41);
+    unsafe {
+        addr.as_mut_ptr::<u64>().write_volatile(*boxed + 1);
+        assert_eq!(addr.as_ptr::<u64>().read_volatile(), 42);
+    }
+    unsafe { Cr3::write(kernel_frame, Cr3::read().1) };
+
+    assert!(!space.is_active());
+    destroy(space);
+}
+
+#[test_case]
+fn destroy_returns_all_frames() {
+    let free_before = free_frames();
+    let space = space_with_page();
+    assert!(free_frames() < free_before);
+    destroy(space);
+    assert_eq!(free_frames(), free_before);
+}
+
+#[test_case]
+fn kernel_half_cannot_be_changed() {
+    let mut space = space_with_page();
+    let kernel_page = Page::containing_address(VirtAddr::new(0x_4444_0000_0000));
+    assert_eq!(
+        space.unmap(kernel_page),
+        Err(MapError::KernelHalf(kernel_page.start_address()))
+    );
+    destroy(space);
+}
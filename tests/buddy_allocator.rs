@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::memory::buddy::{BuddyAllocator, Constraint, MAX_ORDER};
+use spin::Mutex;
+
+entry_point!(main);
+
+static BUDDY: Mutex<Option<BuddyAllocator>> = Mutex::new(None);
+
+/// Number of frames the buddy allocator gets from the frame allocator.
+const BUDDY_FRAMES: usize = 1024;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::memory::BitmapFrameAllocator;
+    use x86_64::{PhysAddr, VirtAddr};
+
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    let frames = frame_allocator
+        .carve_out(BUDDY_FRAMES, PhysAddr::new(16 << 20))
+        .expect("no free range below 16 MiB for the buddy allocator");
+    for frame in frames {
+        assert!(!frame_allocator.is_managed(frame));
+    }
+    let buddy = unsafe { BuddyAllocator::init(frames, phys_mem_offset) };
+    *BUDDY.lock() = Some(buddy);
+
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+#[test_case]
+fn blocks_are_aligned_to_their_size() {
+    let mut guard = BUDDY.lock();
+    let buddy = guard.as_mut().unwrap();
+    for order in [0, 3, 9] {
+        let block = buddy.allocate(order, Constraint::Any).unwrap();
+        assert!(block.start_address().is_aligned(4096u64 << order));
+        unsafe { buddy.deallocate(block, order) };
+    }
+}
+
+#[test_case]
+fn constraints_are_honoured() {
+    let mut guard = BUDDY.lock();
+    let buddy = guard.as_mut().unwrap();
+    let order = 4;
+    let block = buddy.allocate(order, Constraint::Below16MiB).unwrap();
+    assert!(block.start_address().as_u64() + (4096 << order) <= 16 << 20);
+    unsafe { buddy.deallocate(block, order) };
+    let block = buddy.allocate(order, Constraint::Below4GiB).unwrap();
+    assert!(block.start_address().as_u64() + (4096 << order) <= 4 << 30);
+    unsafe { buddy.deallocate(block, order) };
+}
+
+#[test_case]
+fn free_coalesces_buddies() {
+    let mut guard = BUDDY.lock();
+    let buddy = guard.as_mut().unwrap();
+    let free_before = buddy.free_frames();
+    let blocks_before: [usize; MAX_ORDER + 1] = core::array::from_fn(|o| buddy.free_blocks(o));
+
+    let a = buddy.allocate(0, Constraint::Any).unwrap();
+    let b = buddy.allocate(0, Constraint::Any).unwrap();
+    let c = buddy.allocate(2, Constraint::Any).unwrap();
+    assert_eq!(buddy.free_frames(), free_before - 6);
+    unsafe {
+        buddy.deallocate(b, 0);
+        buddy.deallocate(c, 2);
+        buddy.deallocate(a, 0);
+    }
+
+    assert_eq!(buddy.free_frames(), free_before);
+    for (order, &blocks) in blocks_before.iter().enumerate() {
+        assert_eq!(buddy.free_blocks(order), blocks);
+    }
+}
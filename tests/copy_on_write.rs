@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::memory::{cow, demand, vma::Owner, KernelMemory, KERNEL_MEMORY};
+use x86_64::{
+    VirtAddr,
+    structures::paging::{PageTableFlags, PhysFrame, Translate},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os::test_boot(boot_info);
+
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+const FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+fn frame_of(addr: VirtAddr) -> PhysFrame {
+    let memory = KERNEL_MEMORY.lock();
+    let phys = memory.as_ref().unwrap().mapper.translate_addr(addr).unwrap();
+    PhysFrame::containing_address(phys)
+}
+
+fn ref_count(addr: VirtAddr) -> usize {
+    let frame = frame_of(addr);
+    KERNEL_MEMORY.lock().as_ref().unwrap().frame_allocator.ref_count(frame)
+}
+
+/// Reserves two demand-paged pages, writes `value` to the first one and
+/// shares it with the second one.
+fn shared_pair(value: u64) -> (VirtAddr, VirtAddr) {
+    let original = demand::reserve(2 * 4096, Owner::Other("cow test"), FLAGS).unwrap();
+    let copy = original + 4096u64;
+    unsafe { original.as_mut_ptr::<u64>().write_volatile(value) };
+
+    let mut memory = KERNEL_MEMORY.lock();
+    let KernelMemory { mapper, frame_allocator } = memory.as_mut().unwrap();
+    unsafe { cow::share_range(mapper, original, copy, 4096, frame_allocator).unwrap() };
+    (original, copy)
+}
+
+#[test_case]
+fn shared_pages_read_the_same_frame() {
+    let (original, copy) = shared_pair(7);
+    assert_eq!(frame_of(original), frame_of(copy));
+    assert_eq!(ref_count(original), 2);
+    assert_eq!(unsafe { copy.as_ptr::<u64>().read_volatile() }, 7);
+    unsafe { demand::release(original) };
+}
+
+#[test_case]
+fn first_write_copies_the_frame() {
+    let (original, copy) = shared_pair(1);
+    let shared_frame = frame_of(original);
+    unsafe { copy.as_mut_ptr::<u64>().write_volatile(2) };
+
+    assert_eq!(frame_of(original), shared_frame);
+    assert_ne!(frame_of(copy), shared_frame);
+    assert_eq!(ref_count(original), 1);
+    assert_eq!(ref_count(copy), 1);
+    unsafe {
+        assert_eq!(original.as_ptr::<u64>().read_volatile(), 1);
+        assert_eq!(copy.as_ptr::<u64>().read_volatile(), 2);
+    }
+    unsafe { demand::release(original) };
+}
+
+#[test_case]
+fn last_reference_is_made_writable_in_place() {
+    let (original, copy) = shared_pair(1);
+    unsafe { copy.as_mut_ptr::<u64>().write_volatile(2) };
+
+    let frame = frame_of(original);
+    unsafe { original.as_mut_ptr::<u64>().write_volatile(3) };
+    assert_eq!(frame_of(original), frame);
+    assert_eq!(unsafe { original.as_ptr::<u64>().read_volatile() }, 3);
+    unsafe { demand::release(original) };
+}
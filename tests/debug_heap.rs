@@ -0,0 +1,134 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::alloc::{GlobalAlloc, Layout};
+use core::panic::PanicInfo;
+use os::allocator::{
+    self,
+    debug::{DebugHeap, HeapError},
+    linked_list::LinkedListAllocator,
+    Locked,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os::test_boot(boot_info);
+    init_arena();
+
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+const ARENA_SIZE: usize = 4096;
+static mut ARENA: [u64; ARENA_SIZE / 8] = [0; ARENA_SIZE / 8];
+static INNER: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
+static HEAP: DebugHeap<Locked<LinkedListAllocator>> = DebugHeap::new(&INNER);
+
+/// Hands the arena to the wrapped allocator. It must only be called once,
+/// before the first test.
+fn init_arena() {
+    unsafe { INNER.lock().init(&raw mut ARENA as usize, ARENA_SIZE) };
+}
+
+#[test_case]
+fn global_heap_is_consistent() {
+    let before = allocator::check_heap().unwrap();
+    let values: Vec<Box<u64>> = (0..100).map(Box::new).collect();
+    assert_eq!(allocator::check_heap(), Ok(before + 101));
+    drop(values);
+    assert_eq!(allocator::check_heap(), Ok(before));
+}
+
+#[test_case]
+fn debug_heap_detects_errors() {
+    let layout = Layout::from_size_align(32, 8).unwrap();
+
+    let ptr = unsafe { HEAP.alloc(layout) };
+    assert!((0..32).all(|i| unsafe { *ptr.add(i) } == 0xcd));
+    unsafe { ptr.add(32).write(0) };
+    assert_eq!(HEAP.check(), Err(HeapError::Overflow(ptr as usize)));
+    assert_eq!(unsafe { HEAP.try_dealloc(ptr, layout) }, Err(HeapError::Overflow(ptr as usize)));
+    // repair the red zone
+    unsafe { ptr.add(32).write(0xfd) };
+    assert_eq!(HEAP.check(), Ok(1));
+
+    let wrong = Layout::from_size_align(16, 8).unwrap();
+    assert!(matches!(
+        unsafe { HEAP.try_dealloc(ptr, wrong) },
+        Err(HeapError::LayoutMismatch { .. })
+    ));
+    assert_eq!(unsafe { HEAP.try_dealloc(ptr, layout) }, Ok(()));
+    assert_eq!(unsafe { *ptr }, 0xdd);
+    assert_eq!(unsafe { HEAP.try_dealloc(ptr, layout) }, Err(HeapError::DoubleFree(ptr as usize)));
+    assert_eq!(HEAP.check(), Ok(0));
+}
+
+#[test_case]
+fn debug_heap_detects_underflow() {
+    let layout = Layout::from_size_align(64, 16).unwrap();
+    let ptr = unsafe { HEAP.alloc(layout) };
+    assert_eq!(ptr as usize % 16, 0);
+    unsafe { ptr.sub(1).write(0) };
+    assert_eq!(HEAP.check(), Err(HeapError::Underflow(ptr as usize)));
+    unsafe { ptr.sub(1).write(0xfd) };
+    unsafe { HEAP.dealloc(ptr, layout) };
+    assert_eq!(HEAP.check(), Ok(0));
+}
+
+#[test_case]
+fn debug_heap_realloc_keeps_red_zones() {
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    let ptr = unsafe { HEAP.alloc(layout) };
+    unsafe { ptr.write_bytes(1, 16) };
+
+    let ptr = unsafe { HEAP.realloc(ptr, layout, 48) };
+    assert!(!ptr.is_null());
+    assert!((0..16).all(|i| unsafe { *ptr.add(i) } == 1));
+    assert!((16..48).all(|i| unsafe { *ptr.add(i) } == 0xcd));
+    assert_eq!(HEAP.check(), Ok(1));
+    unsafe { ptr.add(48).write(0) };
+    assert_eq!(HEAP.check(), Err(HeapError::Overflow(ptr as usize)));
+    unsafe { ptr.add(48).write(0xfd) };
+
+    let layout = Layout::from_size_align(48, 8).unwrap();
+    let ptr = unsafe { HEAP.realloc(ptr, layout, 8) };
+    assert!((0..8).all(|i| unsafe { *ptr.add(i) } == 1));
+    assert_eq!(HEAP.check(), Ok(1));
+    unsafe { HEAP.dealloc(ptr, Layout::from_size_align(8, 8).unwrap()) };
+    assert_eq!(HEAP.check(), Ok(0));
+}
+
+#[test_case]
+fn debug_heap_realloc_poisons_the_old_block() {
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let old = unsafe { HEAP.alloc(layout) };
+    // keeps the block from growing in place
+    let next = unsafe { HEAP.alloc(layout) };
+    unsafe { old.write_bytes(1, 32) };
+
+    let new = unsafe { HEAP.realloc(old, layout, 512) };
+    assert!(!new.is_null() && new != old);
+    assert!((0..32).all(|i| unsafe { *new.add(i) } == 1));
+    assert!((0..32).all(|i| unsafe { *old.add(i) } == 0xdd));
+    assert_eq!(unsafe { HEAP.try_dealloc(old, layout) }, Err(HeapError::DoubleFree(old as usize)));
+    assert_eq!(HEAP.check(), Ok(2));
+
+    unsafe {
+        HEAP.dealloc(new, Layout::from_size_align(512, 8).unwrap());
+        HEAP.dealloc(next, layout);
+    }
+    assert_eq!(HEAP.check(), Ok(0));
+}
@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::memory::{demand, vma::Owner, KERNEL_MEMORY};
+use x86_64::{
+    VirtAddr,
+    structures::paging::{PageTableFlags, Translate},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os::test_boot(boot_info);
+
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+fn is_mapped(addr: VirtAddr) -> bool {
+    let memory = KERNEL_MEMORY.lock();
+    memory.as_ref().unwrap().mapper.translate_addr(addr).is_some()
+}
+
+fn free_frames() -> usize {
+    KERNEL_MEMORY.lock().as_ref().unwrap().frame_allocator.free_frames()
+}
+
+#[test_case]
+fn pages_are_mapped_on_first_access() {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let start = demand::reserve(4 * 4096, Owner::Other("test buffer"), flags).unwrap();
+    let second_page = start + 4096u64;
+    assert!(!is_mapped(start));
+    assert!(!is_mapped(second_page));
+
+    let ptr: *mut u64 = second_page.as_mut_ptr();
+    unsafe {
+        assert_eq!(ptr.read_volatile(), 0);
+        ptr.write_volatile(42);
+        assert_eq!(ptr.read_volatile(), 42);
+    }
+    assert!(is_mapped(second_page));
+    assert!(!is_mapped(start));
+
+    unsafe { demand::release(start) };
+    assert!(!is_mapped(second_page));
+}
+
+#[test_case]
+fn release_frees_touched_frames() {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let start = demand::reserve(8 * 4096, Owner::Other("test buffer"), flags).unwrap();
+    for page in 0..8u64 {
+        let ptr: *mut u8 = (start + page * 4096).as_mut_ptr();
+        unsafe { ptr.write_volatile(1) };
+    }
+    let free_before_release = free_frames();
+    unsafe { demand::release(start) };
+    assert_eq!(free_frames(), free_before_release + 8);
+}
@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{bootinfo::MemoryRegionType, entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::memory::BitmapFrameAllocator;
+use spin::Mutex;
+use x86_64::{
+    PhysAddr,
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame},
+};
+
+entry_point!(main);
+
+static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+/// A frame from a region of the memory map that is not usable.
+static RESERVED_FRAME: Mutex<Option<PhysFrame>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+    *RESERVED_FRAME.lock() = boot_info
+        .memory_map
+        .iter()
+        .find(|r| r.region_type != MemoryRegionType::Usable)
+        .map(|r| PhysFrame::containing_address(PhysAddr::new(r.range.start_addr())));
+
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+#[test_case]
+fn distinct_frames() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frames = guard.as_mut().unwrap();
+    let a = frames.allocate_frame().unwrap();
+    let b = frames.allocate_frame().unwrap();
+    assert_ne!(a, b);
+    assert!(frames.is_used(a) && frames.is_used(b));
+    unsafe {
+        frames.deallocate_frame(a);
+        frames.deallocate_frame(b);
+    }
+}
+
+#[test_case]
+fn free_count_is_restored() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frames = guard.as_mut().unwrap();
+    let free_before = frames.free_frames();
+    let frame = frames.allocate_frame().unwrap();
+    assert_eq!(frames.free_frames(), free_before - 1);
+    assert_eq!(frames.used_frames() + frames.free_frames(), frames.usable_frames());
+    unsafe { frames.deallocate_frame(frame) };
+    assert_eq!(frames.free_frames(), free_before);
+    assert!(!frames.is_used(frame));
+}
+
+#[test_case]
+fn freed_frame_is_reused() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frames = guard.as_mut().unwrap();
+    let frame = frames.allocate_frame().unwrap();
+    unsafe { frames.deallocate_frame(frame) };
+    assert_eq!(frames.allocate_frame(), Some(frame));
+    unsafe { frames.deallocate_frame(frame) };
+}
+
+#[test_case]
+fn reserved_frames_are_not_managed() {
+    let guard = FRAME_ALLOCATOR.lock();
+    let frames = guard.as_ref().unwrap();
+    let reserved = RESERVED_FRAME.lock().expect("memory map has no reserved region");
+    assert!(!frames.is_managed(reserved));
+    assert!(frames.is_used(reserved));
+    assert_eq!(frames.ref_count(reserved), 0);
+}
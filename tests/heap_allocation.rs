@@ -12,18 +12,7 @@ use core::panic::PanicInfo;
 entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
-    use os::allocator;
-    use os::memory::{self, BootInfoFrameAllocator};
-    use x86_64::VirtAddr;
-
-    os::init();
-    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe {
-        BootInfoFrameAllocator::init(&boot_info.memory_map)
-    };
-    allocator::init_heap(&mut mapper, &mut frame_allocator)
-        .expect("heap initialization failed");
+    os::test_boot(boot_info);
 
     test_main();
     loop {}
@@ -44,7 +33,7 @@ fn simple_allocation() {
     assert_eq!(*heap_value_2, 13);
 }
 
-use alloc::vec::Vec;
+use alloc::{vec, vec::Vec};
 
 #[test_case]
 fn large_vec() {
@@ -56,7 +45,7 @@ fn large_vec() {
     assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
 }
 
-use os::allocator::HEAP_SIZE;
+use os::allocator::{self, HEAP_SIZE};
 
 #[test_case]
 fn many_boxes() {
@@ -74,4 +63,259 @@ fn many_boxes_long_lived() {
         assert_eq!(*x, i);
     }
     assert_eq!(*long_lived, 1); // new
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn heap_grows_beyond_initial_size() {
+    let size_before = allocator::heap_size();
+    let large = vec![1u8; HEAP_SIZE * 2];
+    assert!(allocator::heap_size() > size_before);
+    assert_eq!(large.iter().map(|&b| b as usize).sum::<usize>(), HEAP_SIZE * 2);
+}
+
+// the debug heap adds a header and red zones to every allocation
+#[test_case]
+#[cfg(not(feature = "debug-heap"))]
+fn stats_track_live_allocations() {
+    let before = allocator::heap_stats();
+    let value = Box::new([0u64; 100]);
+    let during = allocator::heap_stats();
+    assert_eq!(during.allocated_bytes, before.allocated_bytes + 800);
+    assert_eq!(during.live_allocations, before.live_allocations + 1);
+    assert!(during.peak_allocated_bytes >= during.allocated_bytes);
+    drop(value);
+    let after = allocator::heap_stats();
+    assert_eq!(after.allocated_bytes, before.allocated_bytes);
+    assert_eq!(after.live_allocations, before.live_allocations);
+    assert!(after.largest_free_block <= after.free_bytes);
+    assert!(after.free_bytes <= after.heap_size);
+}
+
+use core::alloc::{GlobalAlloc, Layout};
+use os::allocator::{
+    linked_list::{Fit, LinkedListAllocator},
+    tlsf::{TlsfAllocator, BLOCK_OVERHEAD},
+    Locked,
+};
+
+const ARENA_SIZE: usize = 4096;
+
+/// Backing memory for the allocators under test.
+static mut ARENA: [u64; ARENA_SIZE / 8] = [0; ARENA_SIZE / 8];
+
+fn linked_list_heap(fit: Fit) -> Locked<LinkedListAllocator> {
+    let heap = Locked::new(LinkedListAllocator::with_fit(fit));
+    unsafe { heap.lock().init(&raw mut ARENA as usize, ARENA_SIZE) };
+    heap
+}
+
+#[test_case]
+fn linked_list_merges_freed_regions() {
+    let heap = linked_list_heap(Fit::First);
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let mut blocks = [core::ptr::null_mut(); ARENA_SIZE / 256];
+    for block in blocks.iter_mut() {
+        *block = unsafe { heap.alloc(layout) };
+        assert!(!block.is_null());
+    }
+    // free every other block first, so that no freed neighbours are adjacent
+    for block in blocks.iter().step_by(2).chain(blocks.iter().skip(1).step_by(2)) {
+        unsafe { heap.dealloc(*block, layout) };
+    }
+    let whole = Layout::from_size_align(ARENA_SIZE, 8).unwrap();
+    let ptr = unsafe { heap.alloc(whole) };
+    assert_eq!(ptr, blocks[0]);
+    unsafe { heap.dealloc(ptr, whole) };
+}
+
+#[test_case]
+fn linked_list_best_fit() {
+    for fit in [Fit::First, Fit::Best] {
+        let heap = linked_list_heap(fit);
+        let sizes = [512, 64, 128, 64];
+        let blocks =
+            sizes.map(|size| unsafe { heap.alloc(Layout::from_size_align(size, 8).unwrap()) });
+        unsafe {
+            heap.dealloc(blocks[0], Layout::from_size_align(512, 8).unwrap());
+            heap.dealloc(blocks[2], Layout::from_size_align(128, 8).unwrap());
+        }
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let ptr = unsafe { heap.alloc(layout) };
+        match fit {
+            Fit::First => assert_eq!(ptr, blocks[0]),
+            Fit::Best => assert_eq!(ptr, blocks[2]),
+        }
+    }
+}
+
+#[test_case]
+fn linked_list_realloc_in_place() {
+    let heap = linked_list_heap(Fit::First);
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let a = unsafe { heap.alloc(layout) };
+    let b = unsafe { heap.alloc(layout) };
+    unsafe { a.write_bytes(0xaa, 256) };
+
+    // `b` is followed by the rest of the heap
+    let grown = unsafe { heap.realloc(b, layout, 1024) };
+    assert_eq!(grown, b);
+    let grown_layout = Layout::from_size_align(1024, 8).unwrap();
+    let shrunk = unsafe { heap.realloc(grown, grown_layout, 128) };
+    assert_eq!(shrunk, b);
+    let shrunk_layout = Layout::from_size_align(128, 8).unwrap();
+
+    // `a` is followed by `b`, so it has to move
+    let moved = unsafe { heap.realloc(a, layout, 512) };
+    assert_ne!(moved, a);
+    assert!((0..256).all(|i| unsafe { *moved.add(i) } == 0xaa));
+    unsafe { heap.dealloc(moved, Layout::from_size_align(512, 8).unwrap()) };
+    unsafe { heap.dealloc(shrunk, shrunk_layout) };
+    assert_eq!(heap.stats().largest_free_block, ARENA_SIZE);
+}
+
+#[test_case]
+fn bump_realloc_last_allocation() {
+    use os::allocator::bump::BumpAllocator;
+
+    let heap = Locked::new(BumpAllocator::new());
+    unsafe { heap.lock().init(&raw mut ARENA as usize, ARENA_SIZE) };
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = unsafe { heap.alloc(layout) };
+    let b = unsafe { heap.alloc(layout) };
+    assert_eq!(unsafe { heap.realloc(b, layout, 1024) }, b);
+    let moved = unsafe { heap.realloc(a, layout, 128) };
+    assert_ne!(moved, a);
+    assert_eq!(heap.stats().free_bytes, ARENA_SIZE - 64 - 1024 - 128);
+}
+
+fn tlsf_heap() -> Locked<TlsfAllocator> {
+    let heap = Locked::new(TlsfAllocator::new());
+    unsafe { heap.lock().init(&raw mut ARENA as usize, ARENA_SIZE) };
+    heap
+}
+
+#[test_case]
+fn tlsf_merges_freed_blocks() {
+    let heap = tlsf_heap();
+    let whole = heap.stats().largest_free_block;
+    assert_eq!(whole, ARENA_SIZE - 2 * BLOCK_OVERHEAD);
+    let layout = Layout::from_size_align(240, 8).unwrap();
+    let mut blocks = [core::ptr::null_mut(); ARENA_SIZE / 256 - 1];
+    for block in blocks.iter_mut() {
+        *block = unsafe { heap.alloc(layout) };
+        assert!(!block.is_null());
+    }
+    for block in blocks.iter().step_by(2).chain(blocks.iter().skip(1).step_by(2)) {
+        unsafe { heap.dealloc(*block, layout) };
+    }
+    let stats = heap.stats();
+    assert_eq!(stats.largest_free_block, whole);
+    assert_eq!(stats.free_bytes, whole);
+}
+
+#[test_case]
+fn tlsf_aligned_allocation() {
+    let heap = tlsf_heap();
+    let small = Layout::from_size_align(8, 8).unwrap();
+    let a = unsafe { heap.alloc(small) };
+    for align in [16, 64, 512] {
+        let layout = Layout::from_size_align(100, align).unwrap();
+        let ptr = unsafe { heap.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % align, 0);
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+    unsafe { heap.dealloc(a, small) };
+    assert_eq!(heap.stats().largest_free_block, ARENA_SIZE - 2 * BLOCK_OVERHEAD);
+}
+
+#[test_case]
+fn tlsf_realloc_in_place() {
+    let heap = tlsf_heap();
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let a = unsafe { heap.alloc(layout) };
+    let b = unsafe { heap.alloc(layout) };
+    unsafe { a.write_bytes(0xaa, 256) };
+
+    // `b` is followed by the rest of the heap
+    let grown = unsafe { heap.realloc(b, layout, 1024) };
+    assert_eq!(grown, b);
+    let grown_layout = Layout::from_size_align(1024, 8).unwrap();
+    let shrunk = unsafe { heap.realloc(grown, grown_layout, 128) };
+    assert_eq!(shrunk, b);
+    let shrunk_layout = Layout::from_size_align(128, 8).unwrap();
+
+    // `a` is followed by `b`, so it has to move
+    let moved = unsafe { heap.realloc(a, layout, 512) };
+    assert_ne!(moved, a);
+    assert!((0..256).all(|i| unsafe { *moved.add(i) } == 0xaa));
+    unsafe { heap.dealloc(moved, Layout::from_size_align(512, 8).unwrap()) };
+    unsafe { heap.dealloc(shrunk, shrunk_layout) };
+    assert_eq!(heap.stats().largest_free_block, ARENA_SIZE - 2 * BLOCK_OVERHEAD);
+}
+
+#[test_case]
+#[cfg(not(any(
+    feature = "bump",
+    feature = "linked-list",
+    feature = "upstream-linked-list",
+    feature = "tlsf",
+)))]
+fn realloc_within_size_class() {
+    let mut vec: Vec<u8> = Vec::with_capacity(20);
+    vec.push(1);
+    let ptr = vec.as_ptr();
+    // 20 and 30 bytes both use 32 byte blocks
+    vec.reserve_exact(29);
+    assert_eq!(vec.as_ptr(), ptr);
+    assert_eq!(vec[0], 1);
+}
+
+#[test_case]
+#[cfg(feature = "alloc-tracker")]
+fn tests_do_not_leak() {
+    allocator::assert_no_leaks(|| {
+        let boxed = Box::new(41);
+        let mut vec = Vec::with_capacity(10);
+        vec.extend(0..1000u64);
+        vec.shrink_to_fit();
+        assert_eq!(vec.iter().sum::<u64>() + *boxed, 999 * 1000 / 2 + 41);
+    });
+}
+
+#[test_case]
+#[cfg(feature = "alloc-tracker")]
+fn tracker_reports_leaks_by_call_site() {
+    let snapshot = allocator::snapshot();
+    let leaked: &'static mut [u64; 4] = Box::leak(Box::new([0; 4]));
+    let kept = Box::new(0u8);
+    let leaks = allocator::leaks_since(&snapshot);
+    assert_eq!(leaks.allocations, 2);
+    assert_eq!(leaks.bytes, 33);
+    assert_eq!(leaks.live_delta, 2);
+    // both boxes may share a call site if the allocation path is deeper than
+    // the recorded return addresses
+    let site = leaks.call_sites().next().unwrap();
+    assert!(site.bytes >= 32);
+    assert_ne!(site.call_site[0], 0);
+    allocator::print_leaks(&snapshot);
+
+    drop(kept);
+    drop(unsafe { Box::from_raw(leaked) });
+    assert!(allocator::leaks_since(&snapshot).is_empty());
+}
+
+#[test_case]
+#[cfg(feature = "alloc-tracker")]
+fn tracker_follows_reallocations() {
+    let snapshot = allocator::snapshot();
+    let mut vec: Vec<u8> = Vec::with_capacity(20);
+    vec.reserve_exact(100);
+    let leaks = allocator::leaks_since(&snapshot);
+    assert_eq!(leaks.allocations, 1);
+    assert_eq!(leaks.bytes, vec.capacity());
+    assert_eq!(leaks.live_delta, 1);
+
+    drop(vec);
+    assert!(allocator::leaks_since(&snapshot).is_empty());
+}
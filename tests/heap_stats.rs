@@ -0,0 +1,176 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::alloc::{GlobalAlloc, Layout};
+use core::panic::PanicInfo;
+use os::allocator::{
+    bump::BumpAllocator, fixed_size_block::FixedSizeBlockAllocator,
+    linked_list::LinkedListAllocator, tlsf::TlsfAllocator, Locked,
+};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    os::init();
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+const ARENA_SIZE: usize = 4096;
+
+#[repr(C, align(4096))]
+struct Arena<const N: usize>([u8; N]);
+
+/// Backing memory for the allocators under test, one arena per test.
+static mut ARENAS: [Arena<ARENA_SIZE>; 4] = [const { Arena([0; ARENA_SIZE]) }; 4];
+
+/// Backing memory for the fixed-size block allocator, which needs room for
+/// page aligned pages. It is page aligned itself, so that the free space
+/// around the pages does not depend on where the linker places it.
+const PAGE_ARENA_SIZE: usize = 4 * 4096;
+static mut PAGE_ARENA: Arena<PAGE_ARENA_SIZE> = Arena([0; PAGE_ARENA_SIZE]);
+
+fn arena(index: usize) -> usize {
+    unsafe { (&raw mut ARENAS[index]) as usize }
+}
+
+#[test_case]
+fn bump_stats() {
+    let bump = Locked::new(BumpAllocator::new());
+    unsafe { bump.lock().init(arena(0), ARENA_SIZE) };
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let ptr = unsafe { bump.alloc(layout) };
+    let stats = bump.stats();
+    assert_eq!(stats.heap_size, ARENA_SIZE);
+    assert_eq!(stats.allocated_bytes, 100);
+    assert_eq!(stats.live_allocations, 1);
+    assert_eq!(stats.free_bytes, ARENA_SIZE - 100);
+    unsafe { bump.dealloc(ptr, layout) };
+    assert_eq!(bump.stats().free_bytes, ARENA_SIZE);
+    assert_eq!(bump.stats().peak_allocated_bytes, 100);
+}
+
+#[test_case]
+fn linked_list_stats() {
+    let heap = Locked::new(LinkedListAllocator::new());
+    unsafe { heap.lock().init(arena(1), ARENA_SIZE) };
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let a = unsafe { heap.alloc(layout) };
+    let b = unsafe { heap.alloc(layout) };
+    unsafe { heap.dealloc(a, layout) };
+    let stats = heap.stats();
+    assert_eq!(stats.live_allocations, 1);
+    assert_eq!(stats.free_bytes, ARENA_SIZE - 1024);
+    // the freed block is not merged with the rest of the heap
+    assert_eq!(stats.largest_free_block, ARENA_SIZE - 2048);
+    unsafe { heap.dealloc(b, layout) };
+}
+
+#[test_case]
+fn tlsf_largest_free_block_scans_the_list() {
+    let heap = Locked::new(TlsfAllocator::new());
+    unsafe { heap.lock().init(arena(3), ARENA_SIZE) };
+    // both blocks end up in the same second-level list
+    let small = Layout::from_size_align(1072, 8).unwrap();
+    let large = Layout::from_size_align(1120, 8).unwrap();
+    let separator = Layout::from_size_align(16, 8).unwrap();
+    let a = unsafe { heap.alloc(small) };
+    let s1 = unsafe { heap.alloc(separator) };
+    let b = unsafe { heap.alloc(large) };
+    let s2 = unsafe { heap.alloc(separator) };
+    let rest = Layout::from_size_align(heap.stats().largest_free_block, 8).unwrap();
+    let c = unsafe { heap.alloc(rest) };
+    assert!(!c.is_null());
+
+    // the smaller block is freed last, so it is the head of the list
+    unsafe {
+        heap.dealloc(b, large);
+        heap.dealloc(a, small);
+    }
+    assert_eq!(heap.stats().largest_free_block, 1120);
+    unsafe {
+        heap.dealloc(c, rest);
+        heap.dealloc(s2, separator);
+        heap.dealloc(s1, separator);
+    }
+}
+
+#[test_case]
+fn fixed_size_block_stats() {
+    let heap = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe { heap.lock().init(&raw mut PAGE_ARENA as usize, PAGE_ARENA_SIZE) };
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let ptr = unsafe { heap.alloc(layout) };
+    assert_eq!(heap.stats().allocated_bytes, 24);
+    unsafe { heap.dealloc(ptr, layout) };
+
+    // the first allocation carves a whole page, which is kept afterwards
+    let classes = heap.lock().size_classes();
+    let class = classes.iter().find(|c| c.block_size == 32).unwrap();
+    assert_eq!(class.pages, 1);
+    assert_eq!(class.free_blocks, (4096 - 32) / 32);
+    let stats = heap.stats();
+    assert_eq!(stats.live_allocations, 0);
+    assert_eq!(stats.free_bytes, PAGE_ARENA_SIZE - 4096);
+    assert!(stats.largest_free_block > 0 && stats.largest_free_block <= stats.free_bytes);
+}
+
+#[test_case]
+fn fixed_size_block_large_classes_span_several_pages() {
+    let heap = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe { heap.lock().init(&raw mut PAGE_ARENA as usize, PAGE_ARENA_SIZE) };
+    let layout = Layout::from_size_align(1000, 8).unwrap();
+    let ptr = unsafe { heap.alloc(layout) };
+    assert!(!ptr.is_null());
+    assert_eq!(ptr as usize % 1024, 0);
+    unsafe { heap.dealloc(ptr, layout) };
+
+    let classes = heap.lock().size_classes();
+    let class = classes.iter().find(|c| c.block_size == 1024).unwrap();
+    assert_eq!(class.page_size, 8 * 1024);
+    assert_eq!(class.pages, 1);
+    // the page header takes the first block
+    assert_eq!(class.free_blocks, 7);
+    assert_eq!(heap.stats().free_bytes, PAGE_ARENA_SIZE - 8 * 1024);
+}
+
+#[test_case]
+fn fixed_size_block_returns_free_pages() {
+    let heap = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe { heap.lock().init(&raw mut PAGE_ARENA as usize, PAGE_ARENA_SIZE) };
+    let free_before = heap.stats().free_bytes;
+    let layout = Layout::from_size_align(64, 64).unwrap();
+    let blocks_per_page = (4096 - 64) / 64;
+
+    let mut blocks = [core::ptr::null_mut(); 100];
+    for block in blocks.iter_mut() {
+        *block = unsafe { heap.alloc(layout) };
+        assert!(!block.is_null());
+    }
+    let class = heap.lock().size_classes()[3];
+    assert_eq!(class.block_size, 64);
+    assert_eq!(class.pages, 2);
+    assert_eq!(class.free_blocks, 2 * blocks_per_page - blocks.len());
+
+    for block in blocks {
+        unsafe { heap.dealloc(block, layout) };
+    }
+    // one page goes back to the fallback heap, which can use it for any size
+    let class = heap.lock().size_classes()[3];
+    assert_eq!(class.pages, 1);
+    assert_eq!(class.free_blocks, blocks_per_page);
+    assert_eq!(heap.stats().free_bytes, free_before - 4096);
+    let large = Layout::from_size_align(2 * 4096, 8).unwrap();
+    let ptr = unsafe { heap.alloc(large) };
+    assert!(!ptr.is_null());
+    unsafe { heap.dealloc(ptr, large) };
+}
@@ -0,0 +1,145 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::memory::{self, BitmapFrameAllocator, mapping};
+use spin::Mutex;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{
+        OffsetPageTable, PageTableFlags, Translate,
+        mapper::{MappedFrame, TranslateResult},
+    },
+};
+
+entry_point!(main);
+
+static MEMORY: Mutex<Option<(OffsetPageTable<'static>, BitmapFrameAllocator)>> = Mutex::new(None);
+
+const TEST_START: u64 = 0x_5000_0000_0000;
+const TWO_MIB: u64 = 2 << 20;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    *MEMORY.lock() = Some((mapper, frame_allocator));
+
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+fn mapped(mapper: &OffsetPageTable, addr: u64) -> (MappedFrame, PageTableFlags) {
+    match mapper.translate(VirtAddr::new(addr)) {
+        TranslateResult::Mapped { frame, flags, .. } => (frame, flags),
+        other => panic!("{:#x} not mapped: {:?}", addr, other),
+    }
+}
+
+#[test_case]
+fn aligned_ranges_use_huge_pages() {
+    let mut guard = MEMORY.lock();
+    let (mapper, frames) = guard.as_mut().unwrap();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe {
+        mapping::map_range(mapper, VirtAddr::new(TEST_START), PhysAddr::new(0),
+            2 * TWO_MIB + 0x1000, flags, frames).unwrap();
+    }
+    assert!(matches!(mapped(mapper, TEST_START).0, MappedFrame::Size2MiB(_)));
+    assert!(matches!(mapped(mapper, TEST_START + TWO_MIB).0, MappedFrame::Size2MiB(_)));
+    assert!(matches!(mapped(mapper, TEST_START + 2 * TWO_MIB).0, MappedFrame::Size4KiB(_)));
+    assert_eq!(
+        mapper.translate_addr(VirtAddr::new(TEST_START + 0x1234)),
+        Some(PhysAddr::new(0x1234))
+    );
+
+    unsafe {
+        mapping::unmap_range(mapper, VirtAddr::new(TEST_START), 2 * TWO_MIB + 0x1000, frames)
+            .unwrap();
+    }
+    assert!(matches!(
+        mapper.translate(VirtAddr::new(TEST_START)),
+        TranslateResult::NotMapped
+    ));
+}
+
+#[test_case]
+fn protecting_a_sub_range_splits_the_huge_page() {
+    let mut guard = MEMORY.lock();
+    let (mapper, frames) = guard.as_mut().unwrap();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe {
+        mapping::map_range(mapper, VirtAddr::new(TEST_START), PhysAddr::new(0),
+            2 * TWO_MIB, flags, frames).unwrap();
+        mapping::protect_range(mapper, VirtAddr::new(TEST_START + 0x1000), 0x1000,
+            PageTableFlags::PRESENT, frames).unwrap();
+    }
+
+    let (frame, flags) = mapped(mapper, TEST_START + 0x1000);
+    assert!(matches!(frame, MappedFrame::Size4KiB(_)));
+    assert!(!flags.contains(PageTableFlags::WRITABLE));
+    let (frame, flags) = mapped(mapper, TEST_START);
+    assert!(matches!(frame, MappedFrame::Size4KiB(_)));
+    assert!(flags.contains(PageTableFlags::WRITABLE));
+    assert!(matches!(mapped(mapper, TEST_START + TWO_MIB).0, MappedFrame::Size2MiB(_)));
+    assert_eq!(
+        mapper.translate_addr(VirtAddr::new(TEST_START + 0x1234)),
+        Some(PhysAddr::new(0x1234))
+    );
+
+    unsafe {
+        mapping::unmap_range(mapper, VirtAddr::new(TEST_START), 2 * TWO_MIB, frames).unwrap();
+    }
+}
+
+#[test_case]
+fn unmapping_frees_empty_page_tables() {
+    let mut guard = MEMORY.lock();
+    let (mapper, frames) = guard.as_mut().unwrap();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let start = VirtAddr::new(TEST_START);
+    // the level 3 table is never freed, so create it first
+    unsafe {
+        mapping::map_range(mapper, start, PhysAddr::new(0), 0x1000, flags, frames).unwrap();
+        mapping::unmap_range(mapper, start, 0x1000, frames).unwrap();
+    }
+
+    let used_before = frames.used_frames();
+    unsafe {
+        mapping::map_range(mapper, start, PhysAddr::new(0), 0x3000, flags, frames).unwrap();
+        assert_eq!(frames.used_frames(), used_before + 2);
+        mapping::unmap_range(mapper, start, 0x3000, frames).unwrap();
+    }
+    assert_eq!(frames.used_frames(), used_before);
+}
+
+#[test_case]
+fn failed_map_range_unmaps_the_mapped_pages() {
+    let mut guard = MEMORY.lock();
+    let (mapper, frames) = guard.as_mut().unwrap();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let start = VirtAddr::new(TEST_START);
+    unsafe {
+        mapping::map_range(mapper, start + 0x2000u64, PhysAddr::new(0), 0x1000, flags, frames)
+            .unwrap();
+        let result = mapping::map_range(mapper, start, PhysAddr::new(0), 0x4000, flags, frames);
+        assert_eq!(result, Err(mapping::MapError::PageAlreadyMapped(start + 0x2000u64)));
+    }
+    assert!(matches!(mapper.translate(start), TranslateResult::NotMapped));
+    assert!(matches!(mapper.translate(start + 0x1000u64), TranslateResult::NotMapped));
+    assert_eq!(mapper.translate_addr(start + 0x2000u64), Some(PhysAddr::new(0)));
+
+    unsafe { mapping::unmap_range(mapper, start + 0x2000u64, 0x1000, frames).unwrap() };
+}
@@ -0,0 +1,125 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![feature(abi_x86_interrupt)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use os::interrupts::{InterruptIndex, PICS};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os::test_boot(boot_info);
+    x86_64::instructions::interrupts::without_interrupts(|| TEST_IDT.load());
+
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(double_fault_handler)
+                .set_stack_index(os::gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt[InterruptIndex::Timer as usize].set_handler_fn(allocating_timer_handler);
+        idt
+    };
+}
+
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Allocations that the timer handler keeps for a few ticks, so that it also
+/// frees memory that the main loop allocated around.
+static KEPT: spin::Mutex<Vec<u64>> = spin::Mutex::new(Vec::new());
+
+/// Makes the timer handler allocate blocks that are large enough to make the
+/// heap grow.
+static LARGE_ALLOCATIONS: AtomicBool = AtomicBool::new(false);
+static LARGE_TICKS: AtomicUsize = AtomicUsize::new(0);
+
+extern "x86-interrupt" fn allocating_timer_handler(_stack_frame: InterruptStackFrame) {
+    let tick = TICKS.load(Ordering::Relaxed);
+    let values: Vec<usize> = (0..32).map(|i| i * tick).collect();
+    assert_eq!(values.iter().sum::<usize>(), 496 * tick);
+
+    let mut kept = KEPT.lock();
+    kept.push(tick as u64);
+    if kept.len() > 4 {
+        kept.clear();
+    }
+    drop(kept);
+
+    if LARGE_ALLOCATIONS.load(Ordering::Relaxed) {
+        let block = alloc::vec![tick as u8; 64 * 1024];
+        assert!(block.iter().all(|&b| b == tick as u8));
+        LARGE_TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    unsafe { PICS.lock().notify_end_of_interrupt(InterruptIndex::Timer as u8) };
+}
+
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+}
+
+#[test_case]
+fn allocate_in_timer_handler() {
+    let start = TICKS.load(Ordering::Relaxed);
+    let mut churn: Vec<Vec<u8>> = Vec::new();
+    let mut round = 0usize;
+    while TICKS.load(Ordering::Relaxed) < start + 10 {
+        churn.push(alloc::vec![round as u8; 1 + round % 300]);
+        if churn.len() > 64 {
+            churn.drain(..32);
+        }
+        round += 1;
+    }
+    for bytes in &churn {
+        assert!(bytes.iter().all(|&b| b == bytes[0]));
+    }
+    // the handler locks `KEPT` as well
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let kept = KEPT.lock();
+        assert!(kept.windows(2).all(|w| w[1] == w[0] + 1));
+    });
+}
+
+#[test_case]
+fn grow_heap_while_timer_handler_allocates() {
+    use os::allocator::heap_size;
+
+    let start_size = heap_size();
+    LARGE_ALLOCATIONS.store(true, Ordering::Relaxed);
+    let start = LARGE_TICKS.load(Ordering::Relaxed);
+    // keep the heap full, so that the main loop and the handler both grow it
+    let mut filled: Vec<Vec<u8>> = Vec::new();
+    while LARGE_TICKS.load(Ordering::Relaxed) < start + 4 {
+        if filled.len() < 512 {
+            filled.push(alloc::vec![1; 32 * 1024]);
+        }
+    }
+    LARGE_ALLOCATIONS.store(false, Ordering::Relaxed);
+    assert!(heap_size() > start_size);
+    assert!(filled.iter().all(|block| block.iter().all(|&b| b == 1)));
+}
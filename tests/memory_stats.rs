@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{bootinfo::MemoryRegionType, entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::allocator;
+use os::memory::stats::{meminfo, MemoryMapSummary};
+use spin::Mutex;
+
+entry_point!(main);
+
+static BOOT_INFO: Mutex<Option<&'static BootInfo>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    assert!(meminfo().is_none());
+    os::test_boot(boot_info);
+    *BOOT_INFO.lock() = Some(boot_info);
+
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+fn summary() -> MemoryMapSummary {
+    MemoryMapSummary::new(&BOOT_INFO.lock().unwrap().memory_map)
+}
+
+#[test_case]
+fn summary_covers_every_region() {
+    let memory_map = &BOOT_INFO.lock().unwrap().memory_map;
+    let summary = MemoryMapSummary::new(memory_map);
+    let regions: usize = summary.iter().map(|t| t.regions).sum();
+    assert_eq!(regions, memory_map.iter().count());
+    let bytes: u64 = memory_map
+        .iter()
+        .map(|r| r.range.end_addr() - r.range.start_addr())
+        .sum();
+    assert_eq!(summary.total_bytes(), bytes);
+    assert!(summary.get(MemoryRegionType::Kernel).is_some());
+}
+
+#[test_case]
+fn usable_memory_matches_frame_allocator() {
+    let usable = summary().get(MemoryRegionType::Usable).unwrap().bytes;
+    let info = meminfo().unwrap();
+    assert_eq!(info.usable_bytes(), usable);
+    assert_eq!(info.used_frames + info.free_frames, info.usable_frames);
+}
+
+#[test_case]
+fn heap_growth_is_reported() {
+    let before = meminfo().unwrap();
+    assert_eq!(before.heap_size, allocator::heap_size());
+    let v: Vec<u8> = Vec::with_capacity(before.heap_size * 2);
+    let after = meminfo().unwrap();
+    assert!(after.heap_size > before.heap_size);
+    assert!(after.used_frames > before.used_frames);
+    drop(v);
+}
+
+#[test_case]
+fn tables_can_be_printed() {
+    os::serial_println!("{}", summary());
+    os::serial_println!("{}", meminfo().unwrap());
+}
+
+#[test_case]
+fn every_region_type_fits_into_the_summary() {
+    use bootloader::bootinfo::{FrameRange, MemoryMap, MemoryRegion};
+    use MemoryRegionType::*;
+
+    let types = [
+        Usable, InUse, Reserved, AcpiReclaimable, AcpiNvs, BadMemory, Kernel, KernelStack,
+        PageTable, Bootloader, FrameZero, Empty, BootInfo, Package, Usable,
+    ];
+    let mut memory_map = MemoryMap::new();
+    for (i, region_type) in (0..).zip(types) {
+        let range = FrameRange::new(i * 4096, (i + 1) * 4096);
+        memory_map.add_region(MemoryRegion { range, region_type });
+    }
+    let summary = MemoryMapSummary::new(&memory_map);
+    assert_eq!(summary.iter().count(), 14);
+    assert_eq!(summary.get(Usable).unwrap().regions, 2);
+    assert_eq!(summary.other(), (0, 0));
+    assert_eq!(summary.total_bytes(), 15 * 4096);
+}
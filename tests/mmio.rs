@@ -0,0 +1,142 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::memory::{
+    mmio::{self, Caching, MmioError},
+    vma::{Owner, Vma, KERNEL_VMAS},
+    walker::PageTableWalker,
+    KERNEL_MEMORY,
+};
+use x86_64::{PhysAddr, VirtAddr, structures::paging::PageTableFlags};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os::test_boot(boot_info);
+
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+/// The VGA text buffer, the only device memory that is always present.
+const VGA_BUFFER: u64 = 0xb8000;
+
+fn leaf_flags(addr: VirtAddr) -> Option<PageTableFlags> {
+    let mut memory = KERNEL_MEMORY.lock();
+    let walker = PageTableWalker::from_mapper(&mut memory.as_mut().unwrap().mapper);
+    walker.translate(addr).ok().map(|translation| translation.flags)
+}
+
+#[test_case]
+fn write_combining_mapping_selects_pat_entry_1() {
+    let vga = unsafe { mmio::map_mmio(PhysAddr::new(VGA_BUFFER), 4000, Caching::WriteCombining) }
+        .unwrap();
+    let flags = leaf_flags(vga.base()).unwrap();
+    assert!(flags.contains(PageTableFlags::WRITE_THROUGH));
+    assert!(!flags.contains(PageTableFlags::NO_CACHE));
+    assert!(flags.contains(PageTableFlags::NO_EXECUTE));
+
+    let pat = unsafe { x86_64::registers::model_specific::Msr::new(0x277).read() };
+    assert_eq!((pat >> 8) & 0xff, 0x01);
+}
+
+#[test_case]
+fn volatile_accessors_reach_the_device() {
+    let vga = unsafe { mmio::map_mmio(PhysAddr::new(VGA_BUFFER), 4000, Caching::Uncached) }
+        .unwrap();
+    let flags = leaf_flags(vga.base()).unwrap();
+    assert!(flags.contains(PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH));
+
+    // last character of the last row
+    let offset = 3998;
+    let old = vga.read::<u16>(offset);
+    vga.write::<u16>(offset, 0x0f21);
+    assert_eq!(vga.read::<u16>(offset), 0x0f21);
+    vga.write(offset, old);
+}
+
+#[test_case]
+fn unaligned_ranges_keep_their_offset() {
+    let vga = unsafe { mmio::map_mmio(PhysAddr::new(VGA_BUFFER + 0x10), 16, Caching::Uncached) }
+        .unwrap();
+    assert_eq!(vga.base().as_u64() % 4096, 0x10);
+    assert_eq!(vga.phys(), PhysAddr::new(VGA_BUFFER + 0x10));
+}
+
+#[test_case]
+fn drop_unmaps_and_releases_the_area() {
+    let vga = unsafe { mmio::map_mmio(PhysAddr::new(VGA_BUFFER), 4000, Caching::Uncached) }
+        .unwrap();
+    let base = vga.base();
+    let owner = KERNEL_VMAS.lock().find(base).unwrap().owner;
+    assert_eq!(owner, Owner::Mmio(PhysAddr::new(VGA_BUFFER)));
+    drop(vga);
+    assert!(leaf_flags(base).is_none());
+    assert!(KERNEL_VMAS.lock().find(base).is_none());
+}
+
+#[test_case]
+fn physical_memory_alias_gets_the_same_memory_type() {
+    use x86_64::structures::paging::Translate;
+
+    let phys_offset = KERNEL_MEMORY.lock().as_ref().unwrap().mapper.phys_offset();
+    let alias = phys_offset + VGA_BUFFER;
+    let vga = unsafe { mmio::map_mmio(PhysAddr::new(VGA_BUFFER), 4000, Caching::Uncached) }
+        .unwrap();
+    let flags = leaf_flags(alias).unwrap();
+    assert!(flags.contains(PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH));
+    // the alias is used by the mapping code, so it must still translate
+    let translated = KERNEL_MEMORY.lock().as_ref().unwrap().mapper.translate_addr(alias);
+    assert_eq!(translated, Some(PhysAddr::new(VGA_BUFFER)));
+
+    drop(vga);
+    let flags = leaf_flags(alias).unwrap();
+    assert!(!flags.intersects(PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH));
+}
+
+#[test_case]
+fn empty_ranges_are_rejected() {
+    let result = unsafe { mmio::map_mmio(PhysAddr::new(VGA_BUFFER), 0, Caching::Uncached) };
+    assert!(matches!(result, Err(MmioError::Empty)));
+}
+
+#[test_case]
+fn overlapping_ranges_are_rejected() {
+    let vga = unsafe { mmio::map_mmio(PhysAddr::new(VGA_BUFFER), 4000, Caching::Uncached) }
+        .unwrap();
+    let result =
+        unsafe { mmio::map_mmio(PhysAddr::new(VGA_BUFFER + 0xf00), 0x200, Caching::WriteBack) };
+    assert!(matches!(
+        result,
+        Err(MmioError::Overlap(Vma { owner: Owner::Mmio(phys), .. }))
+            if phys.as_u64() == VGA_BUFFER
+    ));
+
+    // the next page is not covered by the first handle
+    let next = PhysAddr::new(VGA_BUFFER + 0x1000);
+    assert!(unsafe { mmio::map_mmio(next, 16, Caching::Uncached) }.is_ok());
+    drop(vga);
+}
+
+#[test_case]
+fn drop_with_kernel_memory_locked_keeps_the_mapping() {
+    // the mapping is leaked, so use a page that the other tests don't map
+    let phys = PhysAddr::new(VGA_BUFFER + 0x2000);
+    let vga = unsafe { mmio::map_mmio(phys, 4000, Caching::Uncached) }.unwrap();
+    let base = vga.base();
+    let memory = KERNEL_MEMORY.lock();
+    drop(vga);
+    drop(memory);
+    assert!(leaf_flags(base).is_some());
+    assert_eq!(KERNEL_VMAS.lock().find(base).unwrap().owner, Owner::Mmio(phys));
+}
@@ -0,0 +1,102 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::allocator::slab::ObjectCache;
+use os::memory::KERNEL_MEMORY;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os::test_boot(boot_info);
+
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+/// An odd-sized object that the block allocator would put into a 64 byte block.
+struct Task {
+    id: u64,
+    state: [u8; 32],
+}
+
+impl Task {
+    fn new() -> Self {
+        Task { id: 0, state: [0xaa; 32] }
+    }
+}
+
+static TASKS: ObjectCache<Task> = ObjectCache::new("task", Task::new);
+
+fn free_frames() -> usize {
+    KERNEL_MEMORY.lock().as_ref().unwrap().frame_allocator.free_frames()
+}
+
+#[test_case]
+fn objects_are_constructed() {
+    let task = TASKS.alloc().unwrap();
+    assert_eq!(task.id, 0);
+    assert_eq!(task.state, [0xaa; 32]);
+}
+
+#[test_case]
+fn objects_are_packed_at_their_size() {
+    let stats = TASKS.stats();
+    assert_eq!(stats.object_size, 40);
+    assert!(stats.objects_per_slab > 4096 / 64);
+}
+
+#[test_case]
+fn freed_objects_are_reused_as_they_are() {
+    let mut task = TASKS.alloc().unwrap();
+    task.id = 7;
+    let addr = &*task as *const Task;
+    drop(task);
+    let task = TASKS.alloc().unwrap();
+    assert_eq!(&*task as *const Task, addr);
+    assert_eq!(task.id, 7);
+}
+
+#[test_case]
+fn stats_track_slabs_and_objects() {
+    let cache = ObjectCache::new("stats test", Task::new);
+    let per_slab = cache.stats().objects_per_slab;
+    let objects: Vec<_> = (0..per_slab + 1).map(|_| cache.alloc().unwrap()).collect();
+    let stats = cache.stats();
+    assert_eq!(stats.objects_in_use, per_slab + 1);
+    assert_eq!((stats.full_slabs, stats.partial_slabs, stats.empty_slabs), (1, 1, 0));
+    drop(objects);
+    let stats = cache.stats();
+    assert_eq!(stats.objects_in_use, 0);
+    assert_eq!(stats.empty_slabs, 2);
+    assert_eq!(stats.allocations, stats.frees);
+    os::serial_println!("{}", stats);
+}
+
+#[test_case]
+fn shrink_returns_empty_slabs() {
+    let free_before = free_frames();
+    let cache = ObjectCache::new("shrink test", Task::new);
+    let first = cache.alloc().unwrap();
+    let second = cache.alloc().unwrap();
+    assert_eq!(free_frames(), free_before - 1);
+    drop(first);
+    // the slab still holds an object
+    assert_eq!(cache.shrink(), 0);
+    drop(second);
+    assert_eq!(cache.shrink(), 1);
+    assert_eq!(cache.stats().slabs(), 0);
+    assert_eq!(free_frames(), free_before);
+}
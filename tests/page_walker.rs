@@ -0,0 +1,135 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::{fmt, panic::PanicInfo};
+use os::memory::{mapping, walker::{NotMapped, PageTableWalker}, KERNEL_MEMORY};
+use spin::Mutex;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{PageTable, PageTableFlags, Translate},
+};
+
+entry_point!(main);
+
+static PHYS_MEM_OFFSET: Mutex<u64> = Mutex::new(0);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    *PHYS_MEM_OFFSET.lock() = boot_info.physical_memory_offset;
+    os::test_boot(boot_info);
+
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+#[test_case]
+fn translation_matches_mapper() {
+    let mut memory = KERNEL_MEMORY.lock();
+    let mapper = &mut memory.as_mut().unwrap().mapper;
+    let local = 0u64;
+    let addr = VirtAddr::from_ptr(&raw const local);
+    let expected = mapper.translate_addr(addr).unwrap();
+
+    let walker = PageTableWalker::from_mapper(mapper);
+    let translation = walker.translate(addr).unwrap();
+    assert_eq!(translation.phys, expected);
+    assert_eq!(translation.level, 1);
+    assert!(translation.effective_flags.contains(PageTableFlags::WRITABLE));
+    assert!(walker.translate(VirtAddr::new(0x_7f00_0000_0000)).is_err());
+}
+
+#[test_case]
+fn huge_pages_report_their_level() {
+    let offset = *PHYS_MEM_OFFSET.lock();
+    let walker = unsafe { PageTableWalker::active(VirtAddr::new(offset)) };
+    // the bootloader maps physical memory with 2 MiB pages where possible
+    let translation = walker.translate(VirtAddr::new(offset + 0x20_0000)).unwrap();
+    assert_eq!(translation.level, 2);
+    assert_eq!(translation.page_size, 2 << 20);
+    assert_eq!(translation.phys.as_u64(), 0x20_0000);
+    assert_eq!(
+        walker.translate(VirtAddr::new(0xffff_ff00_0000_0000)),
+        Err(NotMapped { level: 4 })
+    );
+}
+
+#[test_case]
+fn ranges_are_coalesced() {
+    let offset = *PHYS_MEM_OFFSET.lock();
+    let walker = unsafe { PageTableWalker::active(VirtAddr::new(offset)) };
+    let addr = VirtAddr::new(offset + 0x20_0000);
+    let mut phys_map = None;
+    walker.for_each_range(|range| {
+        if range.start <= addr && addr < range.end() {
+            phys_map = Some(range);
+        }
+    });
+    // the physical memory mapping consists of many adjacent 2 MiB pages
+    assert!(phys_map.unwrap().size > 2 << 20);
+
+    let mut ranges = 0;
+    walker.for_each_range(|_| ranges += 1);
+    let mut lines = LineCounter(0);
+    walker.dump(&mut lines).unwrap();
+    assert_eq!(lines.0, ranges);
+}
+
+/// Counts the lines written to it, so that the dump can be checked without
+/// printing it.
+struct LineCounter(usize);
+
+impl fmt::Write for LineCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.matches('\n').count();
+        Ok(())
+    }
+}
+
+#[test_case]
+fn huge_page_pat_bit_is_not_part_of_the_address() {
+    const START: u64 = 0x_5000_0000_0000;
+    const TWO_MIB: u64 = 2 << 20;
+    let mut memory = KERNEL_MEMORY.lock();
+    let memory = memory.as_mut().unwrap();
+    let mapper = &mut memory.mapper;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let start = VirtAddr::new(START);
+    unsafe {
+        mapping::map_range(mapper, start, PhysAddr::new(TWO_MIB), TWO_MIB, flags,
+            &mut memory.frame_allocator).unwrap();
+    }
+
+    // set the PAT bit of the level 2 entry, which lies in the address bits
+    let offset = mapper.phys_offset();
+    let table_at =
+        |phys: PhysAddr| unsafe { &mut *(offset + phys.as_u64()).as_mut_ptr::<PageTable>() };
+    let level_3 = table_at(mapper.level_4_table()[start.p4_index()].addr());
+    let entry = &mut table_at(level_3[start.p3_index()].addr())[start.p2_index()];
+    let entry_flags = entry.flags();
+    entry.set_addr(PhysAddr::new(TWO_MIB | 1 << 12), entry_flags);
+
+    let walker = PageTableWalker::from_mapper(mapper);
+    let translation = walker.translate(start + 0x1234u64).unwrap();
+    assert_eq!(translation.level, 2);
+    assert_eq!(translation.phys.as_u64(), TWO_MIB + 0x1234);
+    let mut range = None;
+    walker.for_each_range(|r| {
+        if r.start == start {
+            range = Some(r);
+        }
+    });
+    assert_eq!(range.unwrap().phys.as_u64(), TWO_MIB);
+
+    entry.set_addr(PhysAddr::new(TWO_MIB), entry_flags);
+    unsafe {
+        mapping::unmap_range(mapper, start, TWO_MIB, &mut memory.frame_allocator).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use os::memory::stack;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("stack_guard::overflow_is_attributed_to_stack...\t");
+
+    os::test_boot(boot_info);
+    // the test IDT only handles double faults
+    x86_64::instructions::interrupts::disable();
+    init_test_idt();
+
+    // run the recursion on a guarded stack instead of the boot stack
+    let test_stack = stack::allocate("test", 4096 * 4).expect("stack allocation failed");
+    unsafe {
+        core::arch::asm!(
+            "mov rsp, {top}",
+            "call {overflow}",
+            top = in(reg) test_stack.top.as_u64(),
+            overflow = sym stack_overflow,
+            options(noreturn),
+        );
+    }
+}
+
+#[allow(unconditional_recursion)]
+extern "C" fn stack_overflow() {
+    stack_overflow(); // for each recursion, the return address is pushed
+    volatile::Volatile::new(0).read(); // prevent tail recursion optimizations
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+use lazy_static::lazy_static;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(os::gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+
+        idt
+    };
+}
+
+fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    use x86_64::registers::control::Cr2;
+
+    match stack::guard_page_owner(Cr2::read()) {
+        Some("test") => {
+            serial_println!("[ok]");
+            exit_qemu(QemuExitCode::Success);
+        }
+        other => {
+            serial_println!("[failed]\n");
+            serial_println!("Error: fault attributed to {:?}\n", other);
+            exit_qemu(QemuExitCode::Failed);
+        }
+    }
+    os::hlt_loop();
+}
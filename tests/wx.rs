@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::memory::{wx, KernelMemory, KERNEL_MEMORY};
+use x86_64::{
+    VirtAddr,
+    structures::paging::{FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    os::test_boot(boot_info);
+    {
+        let mut memory = KERNEL_MEMORY.lock();
+        let KernelMemory { mapper, frame_allocator } = memory.as_mut().unwrap();
+        unsafe { wx::enforce(mapper, frame_allocator) }
+            .expect("failed to remap kernel with W^X permissions");
+    }
+
+    test_main();
+    os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+#[test_case]
+fn no_writable_executable_pages_after_enforce() {
+    let mut memory = KERNEL_MEMORY.lock();
+    let mapper = &mut memory.as_mut().unwrap().mapper;
+    assert_eq!(wx::check(mapper), Ok(()));
+}
+
+#[test_case]
+fn rodata_is_read_only_and_not_executable() {
+    static DATA: u64 = 0;
+    let mut memory = KERNEL_MEMORY.lock();
+    let mapper = &mut memory.as_mut().unwrap().mapper;
+    let walker = os::memory::walker::PageTableWalker::from_mapper(mapper);
+    let translation = walker.translate(VirtAddr::from_ptr(&raw const DATA)).unwrap();
+    assert!(translation.effective_flags.contains(PageTableFlags::NO_EXECUTE));
+    assert!(!translation.effective_flags.contains(PageTableFlags::WRITABLE));
+}
+
+#[test_case]
+fn check_reports_writable_executable_pages() {
+    let mut memory = KERNEL_MEMORY.lock();
+    let KernelMemory { mapper, frame_allocator } = memory.as_mut().unwrap();
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(0x_5100_0000_0000));
+    let frame = frame_allocator.allocate_frame().unwrap();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe { mapper.map_to(page, frame, flags, frame_allocator).unwrap().flush() };
+
+    let range = wx::check(mapper).unwrap_err();
+    assert_eq!(range.start, page.start_address());
+
+    let (frame, flush) = mapper.unmap(page).unwrap();
+    flush.flush();
+    unsafe { frame_allocator.deallocate_frame(frame) };
+    assert_eq!(wx::check(mapper), Ok(()));
+}